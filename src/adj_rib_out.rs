@@ -0,0 +1,70 @@
+/// This module contains the Adj-RIB-Out of the router: the routes last announced
+/// to every neighbor, so that the router can only send what changes.
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::message::Update;
+
+/// Which announcements the router sends to its neighbors.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, ValueEnum)]
+pub enum AdvertiseMode {
+    /// Forward every accepted announcement and every withdrawal to the neighbors the relationships allow.
+    All,
+    /// Only announce the best route to every prefix, and only when it changes,
+    /// so that a withdrawn best route is replaced with the best remaining one.
+    Best,
+}
+
+/// The routes last announced to every neighbor.
+#[derive(Debug, Default)]
+pub struct AdjRibOut {
+    /// Maps neighbor IP addresses to the routes announced to them, by prefix.
+    routes: HashMap<String, BTreeMap<(String, String), Update>>,
+}
+
+impl AdjRibOut {
+    // This function returns the route last announced to a neighbor for the given prefix, if any.
+    pub fn get(&self, neighbor: &str, network: &str, netmask: &str) -> Option<&Update> {
+        self.routes
+            .get(neighbor)?
+            .get(&(network.to_string(), netmask.to_string()))
+    }
+
+    // This function records a route announced to a neighbor, replacing the one announced before.
+    pub fn announce(&mut self, neighbor: &str, update: Update) {
+        let prefix = (update.network.clone(), update.netmask.clone());
+        self.routes
+            .entry(neighbor.to_string())
+            .or_default()
+            .insert(prefix, update);
+    }
+
+    /**
+     * This function records the withdrawal of a prefix from a neighbor.
+     * Return true if a route to the prefix had been announced to the neighbor. Otherwise, false.
+     */
+    pub fn withdraw(&mut self, neighbor: &str, network: &str, netmask: &str) -> bool {
+        self.routes.get_mut(neighbor).is_some_and(|routes| {
+            routes
+                .remove(&(network.to_string(), netmask.to_string()))
+                .is_some()
+        })
+    }
+
+    // This function forgets every route announced to a neighbor.
+    pub fn clear(&mut self, neighbor: &str) {
+        self.routes.remove(neighbor);
+    }
+
+    // This function returns the prefixes of the routes announced to a neighbor.
+    pub fn prefixes(&self, neighbor: &str) -> Vec<(String, String)> {
+        self.routes
+            .get(neighbor)
+            .map_or(vec![], |routes| routes.keys().cloned().collect())
+    }
+
+    // This function returns how many routes are announced to a neighbor.
+    pub fn count(&self, neighbor: &str) -> usize {
+        self.routes.get(neighbor).map_or(0, |routes| routes.len())
+    }
+}
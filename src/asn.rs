@@ -0,0 +1,173 @@
+/// This module contains helpers for 16-bit and 32-bit AS numbers,
+/// written either in asplain ("65536") or in asdot ("1.0") notation (RFC 5396).
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::ops::RangeInclusive;
+
+/// The 16-bit AS numbers reserved for private use (RFC 6996).
+const PRIVATE_16: RangeInclusive<u32> = 64512..=65534;
+/// The 32-bit AS numbers reserved for private use (RFC 6996).
+const PRIVATE_32: RangeInclusive<u32> = 4_200_000_000..=4_294_967_294;
+/// The AS number standing in for 32-bit AS numbers in 16-bit AS paths (RFC 6793).
+const AS_TRANS: u32 = 23456;
+
+/// How AS numbers are written in the rendered table.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, ValueEnum)]
+pub enum AsNotation {
+    /// Every AS number as a single decimal number, e.g. "65536".
+    #[default]
+    Asplain,
+    /// 32-bit AS numbers as two 16-bit decimal numbers separated by a dot, e.g. "1.0".
+    Asdot,
+}
+
+impl AsNotation {
+    /**
+     * This function writes an AS number in the notation.
+     * In asdot, the AS numbers that fit in 16 bits are still written in asplain.
+     * asn: The AS number to write.
+     * Return the written AS number.
+     */
+    pub fn format(&self, asn: u32) -> String {
+        match self {
+            AsNotation::Asdot if asn > u16::MAX as u32 => {
+                format!("{}.{}", asn >> 16, asn & 0xFFFF)
+            }
+            _ => asn.to_string(),
+        }
+    }
+}
+
+/**
+ * This function parses an AS number written in asplain or asdot notation,
+ * optionally prefixed by "AS" (e.g. "AS65001", "4200000000" or "64086.59904").
+ * asn: The AS number to parse.
+ * Return the parsed AS number, or an error if it is not a valid 32-bit AS number.
+ */
+pub fn parse(asn: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid AS number {asn}");
+    let trimmed = asn.trim();
+    let trimmed = trimmed
+        .strip_prefix("AS")
+        .or(trimmed.strip_prefix("as"))
+        .unwrap_or(trimmed);
+
+    match trimmed.split_once('.') {
+        Some((high, low)) => {
+            let high: u16 = high.parse().map_err(|_| invalid())?;
+            let low: u16 = low.parse().map_err(|_| invalid())?;
+            Ok(((high as u32) << 16) | low as u32)
+        }
+        None => trimmed.parse().map_err(|_| invalid()),
+    }
+}
+
+/**
+ * This function checks if an AS number is reserved for private use,
+ * whether it is a 16-bit or a 32-bit AS number.
+ * asn: The AS number to check.
+ * Return true if the AS number is private. Otherwise, false.
+ */
+pub fn is_private(asn: u32) -> bool {
+    PRIVATE_16.contains(&asn) || PRIVATE_32.contains(&asn)
+}
+
+/**
+ * This function checks if an AS number is reserved and may not identify a router,
+ * i.e. AS 0 (RFC 7607), AS_TRANS (RFC 6793) and the last 16-bit and 32-bit AS numbers (RFC 7300).
+ * asn: The AS number to check.
+ * Return true if the AS number is reserved. Otherwise, false.
+ */
+pub fn is_reserved(asn: u32) -> bool {
+    asn == 0 || asn == AS_TRANS || asn == u16::MAX as u32 || asn == u32::MAX
+}
+
+/**
+ * This function reads an AS number from a JSON value,
+ * written either as a number or as an asplain/asdot string.
+ * asn: The JSON value.
+ * Return the AS number, or an error if it is not a valid 32-bit AS number.
+ */
+pub fn from_value(asn: &Value) -> Result<u32, String> {
+    match asn {
+        Value::String(asn) => parse(asn),
+        asn => asn
+            .as_u64()
+            .and_then(|asn| u32::try_from(asn).ok())
+            .ok_or(format!("Invalid AS number {asn}")),
+    }
+}
+
+/// Deserializes an AS path whose AS numbers are written as numbers or as asplain/asdot strings,
+/// rejecting the values that are not 32-bit AS numbers.
+pub fn deserialize_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    let path = Vec::<Value>::deserialize(deserializer)?;
+    path.iter()
+        .map(from_value)
+        .collect::<Result<_, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an optional AS number written as a number or as an asplain/asdot string.
+pub fn deserialize_optional<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u32>, D::Error> {
+    let asn = Option::<Value>::deserialize(deserializer)?;
+    asn.as_ref()
+        .map(from_value)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_accepts_asplain_and_asdot() {
+        assert_eq!(parse("65001"), Ok(65001));
+        assert_eq!(parse("AS65001"), Ok(65001));
+        assert_eq!(parse("as65001"), Ok(65001));
+        assert_eq!(parse("4294967295"), Ok(u32::MAX));
+        assert_eq!(parse("1.0"), Ok(65536));
+        assert_eq!(parse("64086.59904"), Ok(4_200_000_000));
+        assert_eq!(parse("65535.65535"), Ok(u32::MAX));
+        assert_eq!(parse("0.65001"), Ok(65001));
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_values() {
+        for asn in [
+            "4294967296",
+            "-1",
+            "65536.0",
+            "1.65536",
+            "1.",
+            ".1",
+            "1.2.3",
+            "AS",
+            "",
+        ] {
+            assert!(parse(asn).is_err(), "accepted {asn:?}");
+        }
+    }
+
+    #[test]
+    fn asdot_round_trips() {
+        assert_eq!(AsNotation::Asdot.format(4_200_000_000), "64086.59904");
+        assert_eq!(AsNotation::Asdot.format(65001), "65001");
+        assert_eq!(AsNotation::Asplain.format(4_200_000_000), "4200000000");
+        assert_eq!(parse(&AsNotation::Asdot.format(65536)), Ok(65536));
+    }
+
+    #[test]
+    fn from_value_checks_the_range() {
+        assert_eq!(from_value(&json!(65001)), Ok(65001));
+        assert_eq!(from_value(&json!("1.0")), Ok(65536));
+        assert!(from_value(&json!(4_294_967_296u64)).is_err());
+        assert!(from_value(&json!(-1)).is_err());
+        assert!(from_value(&json!(1.5)).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+/// This module contains the BGP communities attribute (RFC 1997)
+/// with its well-known communities, and the large communities attribute (RFC 8092).
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// A BGP community, written as "asn:value" (e.g. "65001:100").
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct Community(pub u32);
+
+impl Community {
+    /// Routes carrying this community must not be advertised outside of our AS.
+    pub const NO_EXPORT: Community = Community(0xFFFF_FF01);
+    /// Routes carrying this community must not be advertised to any neighbor.
+    pub const NO_ADVERTISE: Community = Community(0xFFFF_FF02);
+    /// Routes carrying this community must not be advertised to external peers,
+    /// including the other members of our confederation.
+    pub const NO_EXPORT_SUBCONFED: Community = Community(0xFFFF_FF03);
+
+    /**
+     * This function checks if routes carrying the given communities may be
+     * advertised to a neighbor.
+     * communities: The communities of the route.
+     * external: Whether the neighbor is in another AS than ours.
+     * Return true if the well-known communities permit the advertisement. Otherwise, false.
+     */
+    pub fn permits_export(communities: &[Community], external: bool) -> bool {
+        if communities.contains(&Community::NO_ADVERTISE) {
+            return false;
+        }
+        if external
+            && (communities.contains(&Community::NO_EXPORT)
+                || communities.contains(&Community::NO_EXPORT_SUBCONFED))
+        {
+            return false;
+        }
+        true
+    }
+}
+
+impl fmt::Display for Community {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.0 >> 16, self.0 & 0xFFFF)
+    }
+}
+
+impl std::str::FromStr for Community {
+    type Err = String;
+
+    /// Parses "asn:value" or the name of a well-known community (e.g. "no-export").
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "no-export" => return Ok(Community::NO_EXPORT),
+            "no-advertise" => return Ok(Community::NO_ADVERTISE),
+            "no-export-subconfed" | "local-as" => return Ok(Community::NO_EXPORT_SUBCONFED),
+            _ => {}
+        }
+
+        let (asn, value) = s.split_once(':').ok_or(format!("Invalid community {s}"))?;
+        let asn: u16 = asn.parse().map_err(|_| format!("Invalid community {s}"))?;
+        let value: u16 = value
+            .parse()
+            .map_err(|_| format!("Invalid community {s}"))?;
+        Ok(Community(((asn as u32) << 16) | value as u32))
+    }
+}
+
+impl Serialize for Community {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Community {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A BGP large community, written as "asn:data1:data2" (e.g. "65001:100:7").
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct LargeCommunity {
+    /// The AS that defined the community.
+    pub asn: u32,
+    pub data1: u32,
+    pub data2: u32,
+}
+
+impl fmt::Display for LargeCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.asn, self.data1, self.data2)
+    }
+}
+
+impl std::str::FromStr for LargeCommunity {
+    type Err = String;
+
+    /// Parses "asn:data1:data2".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 3 {
+            return Err(format!("Invalid large community {s}"));
+        }
+        let parse = |part: &str| {
+            part.parse::<u32>()
+                .map_err(|_| format!("Invalid large community {s}"))
+        };
+        Ok(LargeCommunity {
+            asn: parse(parts[0])?,
+            data1: parse(parts[1])?,
+            data2: parse(parts[2])?,
+        })
+    }
+}
+
+impl Serialize for LargeCommunity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LargeCommunity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
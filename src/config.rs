@@ -0,0 +1,235 @@
+/// This module contains the router configuration file,
+/// which holds the policies that are configured per neighbor.
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::asn;
+use crate::policy::{AsPathAccessList, CommunityList, PrefixList, RouteMap};
+use crate::role::Role;
+use crate::router::NeighborType;
+use crate::routing_table::Network;
+
+lazy_static! {
+    // The configuration of the neighbors that are not configured
+    static ref DEFAULT_NEIGHBOR: NeighborConfig = NeighborConfig::default();
+}
+
+/// The router configuration, loaded from a JSON file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouterConfig {
+    /// Maps neighbor IP addresses to their configuration.
+    pub neighbors: HashMap<String, NeighborConfig>,
+    /// How the localpref of received routes is assigned.
+    pub localpref: LocalPrefConfig,
+    /// Named prefix lists, referenced by route-maps.
+    pub prefix_lists: HashMap<String, PrefixList>,
+    /// Named AS-path access lists, referenced by route-maps.
+    pub as_path_lists: HashMap<String, AsPathAccessList>,
+    /// Named community lists, referenced by route-maps.
+    pub community_lists: HashMap<String, CommunityList>,
+    /// Named route-maps, referenced by the neighbors.
+    pub route_maps: HashMap<String, RouteMap>,
+}
+
+/// How the localpref of a received route is assigned.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalPrefMode {
+    /// Keep the localpref sent by the neighbor.
+    #[default]
+    Received,
+    /// Replace the localpref with the value of the neighbor's relationship.
+    Override,
+    /// Add the localpref sent by the neighbor, clamped to 0..=99,
+    /// to the value of the neighbor's relationship.
+    Combine,
+}
+
+/// Which private AS numbers are removed from the paths of the routes sent to a neighbor.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemovePrivateAs {
+    /// Keep the path as it is.
+    #[default]
+    Off,
+    /// Remove every private AS number from the path.
+    All,
+    /// Remove the private AS numbers only if the path consists of nothing else.
+    IfAllPrivate,
+}
+
+impl RemovePrivateAs {
+    /**
+     * This function removes the private AS numbers from the given path.
+     * as_path: The AS path, which gets changed in place.
+     */
+    pub fn apply(&self, as_path: &mut Vec<u32>) {
+        let private = |asn: &u32| asn::is_private(*asn);
+        match self {
+            RemovePrivateAs::Off => {}
+            RemovePrivateAs::All => as_path.retain(|asn| !private(asn)),
+            RemovePrivateAs::IfAllPrivate => {
+                if as_path.iter().all(private) {
+                    as_path.clear();
+                }
+            }
+        }
+    }
+}
+
+/// Assigns the localpref of received routes from the relationship with the neighbor,
+/// so that customer routes are preferred over peer routes, which are preferred over provider routes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalPrefConfig {
+    pub mode: LocalPrefMode,
+    /// The localpref of the routes received from customers.
+    pub cust: i32,
+    /// The localpref of the routes received from peers.
+    pub peer: i32,
+    /// The localpref of the routes received from providers.
+    pub prov: i32,
+}
+
+impl Default for LocalPrefConfig {
+    fn default() -> Self {
+        LocalPrefConfig {
+            mode: LocalPrefMode::Received,
+            cust: 300,
+            peer: 200,
+            prov: 100,
+        }
+    }
+}
+
+impl LocalPrefConfig {
+    /**
+     * This function assigns the localpref of a route received from a neighbor.
+     * relation: The relationship with the neighbor.
+     * received: The localpref sent by the neighbor.
+     * Return the localpref to install the route with.
+     */
+    pub fn assign(&self, relation: &NeighborType, received: i32) -> i32 {
+        let value = match relation {
+            NeighborType::Cust => self.cust,
+            NeighborType::Peer => self.peer,
+            NeighborType::Prov => self.prov,
+        };
+
+        match self.mode {
+            LocalPrefMode::Received => received,
+            LocalPrefMode::Override => value,
+            LocalPrefMode::Combine => value + received.clamp(0, 99),
+        }
+    }
+}
+
+/// The configuration of a single neighbor.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NeighborConfig {
+    /// The AS number of the neighbor. Otherwise, it is learned from the handshake, if sent.
+    #[serde(deserialize_with = "asn::deserialize_optional")]
+    pub asn: Option<u32>,
+    /// Our BGP role on the session with the neighbor. Otherwise, it follows from the relationship.
+    pub role: Option<Role>,
+    /// Filters the AS paths of the routes received from the neighbor.
+    pub import_as_path: AsPathAccessList,
+    /// Filters the AS paths of the routes sent to the neighbor.
+    pub export_as_path: AsPathAccessList,
+    /// Filters the routes received from the neighbor by their (large) communities.
+    pub import_community: CommunityList,
+    /// Filters the routes sent to the neighbor by their (large) communities.
+    pub export_community: CommunityList,
+    /// The name of the route-map applied to the routes received from the neighbor.
+    pub import_route_map: Option<String>,
+    /// The name of the route-map applied to the routes sent to the neighbor.
+    pub export_route_map: Option<String>,
+    /// How many extra times our AS number is prepended to the routes sent to the neighbor.
+    pub prepend: usize,
+    /// Which private AS numbers are removed from the routes sent to the neighbor.
+    pub remove_private_as: RemovePrivateAs,
+}
+
+impl RouterConfig {
+    // This function loads the router configuration from the given JSON file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("{e} -> failed to read {path}"))?;
+        let config: RouterConfig = serde_json::from_str(&content)
+            .map_err(|e| format!("{e} -> invalid configuration {path}"))?;
+        config
+            .validate()
+            .map_err(|e| format!("{e} -> invalid configuration {path}"))?;
+        Ok(config)
+    }
+
+    // This function checks that every referenced route-map and list is defined.
+    fn validate(&self) -> Result<(), String> {
+        for (ip_addr, neighbor) in self.neighbors.iter() {
+            for name in [&neighbor.import_route_map, &neighbor.export_route_map]
+                .into_iter()
+                .flatten()
+            {
+                if !self.route_maps.contains_key(name) {
+                    return Err(format!("Undefined route-map {name} for neighbor {ip_addr}"));
+                }
+            }
+        }
+
+        for (name, route_map) in self.route_maps.iter() {
+            for entry in route_map.entries.iter() {
+                let conditions = &entry.conditions;
+                if let Some(list) = &conditions.prefix_list {
+                    if !self.prefix_lists.contains_key(list) {
+                        return Err(format!("Undefined prefix list {list} in route-map {name}"));
+                    }
+                }
+                if let Some(list) = &conditions.as_path {
+                    if !self.as_path_lists.contains_key(list) {
+                        return Err(format!("Undefined AS-path list {list} in route-map {name}"));
+                    }
+                }
+                if let Some(list) = &conditions.community {
+                    if !self.community_lists.contains_key(list) {
+                        return Err(format!(
+                            "Undefined community list {list} in route-map {name}"
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * This function applies a route-map of a neighbor to the given route.
+     * name: The name of the route-map, None if the neighbor has no route-map.
+     * net: The route, which gets changed by the route-map.
+     * source: The relationship with the neighbor the route was learned from.
+     * asn: Our AS number, used for prepending.
+     * Return true if the route is permitted. Otherwise, false.
+     */
+    pub fn apply_route_map(
+        &self,
+        name: &Option<String>,
+        net: &mut Network,
+        source: &NeighborType,
+        asn: u32,
+    ) -> bool {
+        match name {
+            Some(name) => self.route_maps[name].apply(net, source, asn, self),
+            None => true,
+        }
+    }
+
+    /**
+     * This function returns the configuration of the given neighbor.
+     * Neighbors that are not configured get the default configuration.
+     */
+    pub fn neighbor(&self, ip_addr: &str) -> &NeighborConfig {
+        self.neighbors.get(ip_addr).unwrap_or(&DEFAULT_NEIGHBOR)
+    }
+}
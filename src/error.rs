@@ -0,0 +1,70 @@
+/// This module contains the errors that can occur while the router
+/// handles the messages of its neighbors.
+use std::{fmt, io};
+
+/// An error that occurred while handling a message or running the event loop.
+#[derive(Debug)]
+pub enum RouterError {
+    /// The datagram does not fit in the read buffer.
+    Incomplete,
+    /// The datagram is not a JSON message with "src", "dst", "type" and "msg" fields.
+    Parse(serde_json::Error),
+    /// The body of a message of the given type is invalid.
+    InvalidMessage { r#type: String, reason: String },
+    /// A message of the given type could not be sent.
+    Send { r#type: String, source: io::Error },
+    /// The sockets could not be set up or polled.
+    Io { context: String, source: io::Error },
+}
+
+impl RouterError {
+    // This function creates the error of an invalid message body.
+    pub fn invalid(r#type: &str, reason: impl fmt::Display) -> Self {
+        RouterError::InvalidMessage {
+            r#type: r#type.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    // This function returns a mapper from a send failure to the error of the given message type.
+    pub fn send(r#type: &str) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| RouterError::Send {
+            r#type: r#type.to_string(),
+            source,
+        }
+    }
+
+    // This function returns a mapper from a socket failure to the error with the given context.
+    pub fn io(context: String) -> impl FnOnce(io::Error) -> Self {
+        move |source| RouterError::Io { context, source }
+    }
+
+    /**
+     * This function checks if the error is caused by the received message,
+     * rather than by the router failing to send its reply.
+     */
+    pub fn is_malformed(&self) -> bool {
+        matches!(
+            self,
+            RouterError::Incomplete | RouterError::Parse(_) | RouterError::InvalidMessage { .. }
+        )
+    }
+}
+
+impl fmt::Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouterError::Incomplete => write!(f, "Data incomplete"),
+            RouterError::Parse(e) => write!(f, "{e} -> failed to parse JSON object"),
+            RouterError::InvalidMessage { r#type, reason } => {
+                write!(f, "{reason} -> invalid {type} message")
+            }
+            RouterError::Send { r#type, source } => {
+                write!(f, "{source} -> failed to send {type} message")
+            }
+            RouterError::Io { context, source } => write!(f, "{source} -> {context}"),
+        }
+    }
+}
+
+impl std::error::Error for RouterError {}
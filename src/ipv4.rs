@@ -1,4 +1,6 @@
 use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::net::Ipv4Addr;
 
 // Avoid initialization of static variable until it is actually needed
 lazy_static! {
@@ -24,6 +26,45 @@ pub fn to_decimal(ip: &str) -> u32 {
     (quad1 << 24) + (quad2 << 16) + (quad3 << 8) + quad4
 }
 
+/**
+ * This function parses an IPv4 address received from the outside, such as a neighbor.
+ * Unlike to_decimal, it never panics.
+ * ip: The IP address, which must be a dotted quad.
+ * Return the decimal format of the given IP address, or an error if it is not a dotted quad.
+ */
+pub fn parse(ip: &str) -> Result<u32, String> {
+    ip.parse::<Ipv4Addr>()
+        .map(u32::from)
+        .map_err(|_| format!("Invalid IPv4 address {ip}"))
+}
+
+/**
+ * This function parses a subnet mask received from the outside, such as a neighbor.
+ * mask: The subnet mask, which must be a dotted quad whose one bits are contiguous.
+ * Return the decimal format of the given subnet mask, or an error if it is not a valid subnet mask.
+ */
+pub fn parse_netmask(mask: &str) -> Result<u32, String> {
+    let mask_deci = parse(mask)?;
+    if mask_deci.leading_ones() + mask_deci.trailing_zeros() < 32 {
+        return Err(format!("Invalid netmask {mask}"));
+    }
+    Ok(mask_deci)
+}
+
+/// Deserializes an IPv4 address, rejecting the strings that are not dotted quads.
+pub fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let ip = String::deserialize(deserializer)?;
+    parse(&ip).map_err(serde::de::Error::custom)?;
+    Ok(ip)
+}
+
+/// Deserializes a subnet mask, rejecting the strings that are not dotted quads with contiguous one bits.
+pub fn deserialize_netmask<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let mask = String::deserialize(deserializer)?;
+    parse_netmask(&mask).map_err(serde::de::Error::custom)?;
+    Ok(mask)
+}
+
 /**
  * This function converts a decimal representation of an IPv4 address
  * back into its string representation.
@@ -86,6 +127,19 @@ pub fn netmask_digit(mask: &str) -> i32 {
     b_str.bytes().filter(|&x| x == b'1').count() as i32
 }
 
+/**
+ * This function transforms a netmask from digit format to IPv4 format.
+ * i.g. 16 => 255.255.0.0
+ * digit: The digit format of a subnet mask (0 to 32).
+ * Return the subnet mask in IPv4 format.
+ */
+pub fn netmask_from_digit(digit: i32) -> String {
+    if digit <= 0 {
+        return to_ipv4(0);
+    }
+    to_ipv4(u32::MAX << (32 - digit.min(32)))
+}
+
 /**
  * This function transforms a given subnet mask in IPv4 format to another subnet mask in IPv4 format
  * whose digit format is increased by one.
@@ -109,7 +163,7 @@ pub fn netnask_increase(mask: &str) -> String {
  * Return a tuple of the divided sub-prefixes.
  */
 pub fn divide_prefix(prefix: &str, mask: &str) -> (String, String) {
-    if apply_mask_prefix(prefix, mask) % 2 == 0 {
+    if apply_mask_prefix(prefix, mask) & 1 == 0 {
         let divided_prefix = to_ipv4(to_decimal(prefix) + (1 << (32 - netmask_digit(mask))));
         return (prefix.to_string(), divided_prefix.to_string());
     }
@@ -134,3 +188,26 @@ pub fn check_match(prefix: &str, netmask: &str, network: &str) -> bool {
 
     prefix_deci == net_prefix_deci
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_dotted_quads_only() {
+        assert_eq!(parse("10.0.1.2"), Ok(0x0A00_0102));
+        assert!(parse("not-an-ip").is_err());
+        assert!(parse("1.2.3").is_err());
+        assert!(parse("1.2.3.256").is_err());
+        assert!(parse("1.2.3.4.5").is_err());
+    }
+
+    #[test]
+    fn parse_netmask_rejects_holes() {
+        assert_eq!(parse_netmask("255.255.0.0"), Ok(0xFFFF_0000));
+        assert_eq!(parse_netmask("0.0.0.0"), Ok(0));
+        assert_eq!(parse_netmask("255.255.255.255"), Ok(u32::MAX));
+        assert!(parse_netmask("255.0.255.0").is_err());
+        assert!(parse_netmask("0.255.255.255").is_err());
+    }
+}
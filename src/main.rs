@@ -1,31 +1,207 @@
-use clap::Parser;
+use adj_rib_out::AdvertiseMode;
+use asn::AsNotation;
+use clap::{Parser, Subcommand};
+use role::LeakPolicy;
 use router::Router;
+use routing_table::Table;
+use rpki::RpkiPolicy;
 
+mod adj_rib_out;
+mod asn;
+mod community;
+mod config;
+mod error;
 mod ipv4;
+mod message;
+mod mrt;
+mod policy;
+mod recorder;
+mod role;
 mod router;
 mod routing_table;
+mod rpki;
+mod session;
+mod show;
 #[macro_use]
 extern crate lazy_static;
 
 #[derive(Parser, Debug)]
-#[command(author, about, long_about = None)]
+#[command(
+    author,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    asn: u8,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(required = true, value_parser = parse_router_asn)]
+    asn: Option<u32>,
     neighbors: Vec<String>,
+    /// Load the per-neighbor policies from this JSON configuration file
+    #[arg(long)]
+    config: Option<String>,
+    /// Write the routing table as an MRT TABLE_DUMP_V2 file to this path on every "dump"
+    #[arg(long)]
+    mrt_dump: Option<String>,
+    /// Record every received "update" and "withdraw" message as JSON lines to this path
+    #[arg(long)]
+    record: Option<String>,
+    /// Validate route origins against the ROAs of this JSON or CSV export
+    #[arg(long)]
+    roa_file: Option<String>,
+    /// What to do with RPKI invalid routes
+    #[arg(long, value_enum, default_value_t = RpkiPolicy::Accept)]
+    rpki_policy: RpkiPolicy,
+    /// What to do with routes detected as route leaks by their OTC attribute
+    #[arg(long, value_enum, default_value_t = LeakPolicy::Off)]
+    leak_policy: LeakPolicy,
+    /// Accept routes whose path contains our AS up to this many times
+    #[arg(long, default_value_t = 0)]
+    allowas_in: usize,
+    /// How AS numbers are written in "show" replies
+    #[arg(long, value_enum, default_value_t = AsNotation::Asplain)]
+    as_notation: AsNotation,
+    /// Which announcements are sent to the neighbors; only "best" replaces a withdrawn best route
+    /// with the best remaining one, "all" forwards the withdrawal
+    #[arg(long, value_enum, default_value_t = AdvertiseMode::All)]
+    advertise: AdvertiseMode,
+    /// Declare a neighbor down after this many seconds without a message, 0 to disable
+    #[arg(long, default_value_t = 0)]
+    hold_time: u64,
+    /// Report malformed messages back to their sender with an "error" message
+    #[arg(long)]
+    notify_errors: bool,
+}
+
+// Offline tools that run without starting the router.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render a saved "table" message as "show ip bgp" text
+    ShowTable {
+        /// Path of the saved "table" message
+        file: String,
+        /// How AS numbers are written
+        #[arg(long, value_enum, default_value_t = AsNotation::Asplain)]
+        as_notation: AsNotation,
+    },
+    /// Convert a saved "table" message into an MRT TABLE_DUMP_V2 file
+    TableToMrt {
+        /// Path of the saved "table" message
+        file: String,
+        /// Path of the MRT file to write
+        output: String,
+    },
+    /// Print the routes of an MRT TABLE_DUMP_V2 file as a "table" message body
+    MrtToTable {
+        /// Path of the MRT file
+        file: String,
+    },
+    /// Replay a recording into a fresh table and print its final state
+    Replay {
+        /// Path of the recording
+        file: String,
+        /// Print the final table as "show ip bgp" text instead of JSON
+        #[arg(long)]
+        show: bool,
+        /// How AS numbers are written with --show
+        #[arg(long, value_enum, default_value_t = AsNotation::Asplain)]
+        as_notation: AsNotation,
+    },
+}
+
+/// Parses our AS number, which must be a 32-bit AS number that is not reserved.
+fn parse_router_asn(asn: &str) -> Result<u32, String> {
+    let parsed = asn::parse(asn)?;
+    if asn::is_reserved(parsed) {
+        return Err(format!("AS number {asn} is reserved"));
+    }
+    Ok(parsed)
+}
+
+/// Runs an offline tool and exits.
+fn run_command(command: Command) -> Result<(), String> {
+    match command {
+        Command::ShowTable { file, as_notation } => {
+            print!("{}", show::render_table_file(&file, as_notation)?);
+        }
+        Command::TableToMrt { file, output } => {
+            let table = Table::load(&file)?;
+            std::fs::write(&output, mrt::export_table(table.get_table()))
+                .map_err(|e| format!("{e} -> failed to write {output}"))?;
+        }
+        Command::MrtToTable { file } => {
+            let data = std::fs::read(&file).map_err(|e| format!("{e} -> failed to read {file}"))?;
+            let table = mrt::import_table(&data)?;
+            println!("{}", serde_json::json!(table.get_table()));
+        }
+        Command::Replay {
+            file,
+            show,
+            as_notation,
+        } => {
+            let table = recorder::replay(&file)?;
+            if show {
+                print!("{}", show::render_table(&table, as_notation));
+            } else {
+                println!("{}", serde_json::json!(table.get_table()));
+            }
+        }
+    }
+    Ok(())
 }
 
 fn main() {
     // Parse command line arguments into a `Cli` struct.
     let cli = Cli::parse();
-    // Assign AS number
-    match Router::assign_asn(cli.asn) {
-        Ok(_) => {
-            println!("Successfully assigned AS number")
+    // Run the offline tool instead of the router if one was requested
+    if let Some(command) = cli.command {
+        if let Err(e) = run_command(command) {
+            eprintln!("Error : {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Create the router with its AS number
+    let mut router = Router::new(cli.asn.unwrap_or_default());
+    // Write MRT snapshots of the table if requested
+    if let Some(path) = cli.mrt_dump {
+        router.set_mrt_dump(path);
+    }
+    // Record the received messages if requested
+    if let Some(path) = cli.record {
+        if let Err(e) = router.set_recorder(&path) {
+            eprintln!("{} -> Failed to set up the recording", e);
+            std::process::exit(1);
         }
-        Err(e) => {
-            println!("{}", format!("{} -> Failed to assign AS number", e))
+    }
+    // Load the per-neighbor policies if a configuration file was given
+    if let Some(path) = cli.config {
+        if let Err(e) = router.load_config(&path) {
+            eprintln!("{} -> Failed to load the configuration", e);
+            std::process::exit(1);
         }
     }
+    // Allow our own AS in received paths if requested
+    router.set_allowas_in(cli.allowas_in);
+    // Drop or flag route leaks as requested
+    router.set_leak_policy(cli.leak_policy);
+    // Write AS numbers in the requested notation
+    router.set_as_notation(cli.as_notation);
+    // Report malformed messages to their sender if requested
+    router.set_notify_errors(cli.notify_errors);
+    // Load the ROA set if route origins should be validated
+    if let Some(path) = cli.roa_file {
+        if let Err(e) = router.load_roas(&path, cli.rpki_policy) {
+            eprintln!("{} -> Failed to load the ROA set", e);
+            std::process::exit(1);
+        }
+    }
+    // Only announce the changes of the best routes if requested
+    router.set_advertise_mode(cli.advertise);
+    // Take silent neighbors down after the hold time
+    router.set_hold_time(cli.hold_time);
     // Iterate over each neighbor specified in the command line arguments.
     for neighbor in &cli.neighbors {
         let neighbor_information: Vec<_> = neighbor.split('-').collect();
@@ -36,9 +212,8 @@ fn main() {
             neighbor_information[2],
         );
 
-        // Attempt to add the neighbor to the global router. `Router::add_neighbor` updates
-        // the global router instance with the new neighbor's details.
-        match Router::add_neighbor(neighbor_ip, neighbor_port, neighbor_relation) {
+        // Attempt to add the neighbor to the router with its details.
+        match router.add_neighbor(neighbor_ip, neighbor_port, neighbor_relation) {
             Ok(()) => {
                 println!("Router created successfully");
             }
@@ -49,5 +224,8 @@ fn main() {
     }
 
     // Start the router.
-    Router::start_router().unwrap();
+    if let Err(e) = router.start_router() {
+        eprintln!("Error : {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,274 @@
+/// This module contains the protocol messages exchanged between the router
+/// and its neighbors, which are JSON objects with "src", "dst", "type" and "msg" fields.
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    asn,
+    community::{Community, LargeCommunity},
+    ipv4,
+    routing_table::{Network, Origin},
+};
+
+/// A message that can be sent between routers.
+/// Its addresses must be dotted quads.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Message {
+    #[serde(deserialize_with = "ipv4::deserialize_address")]
+    pub src: String,
+    #[serde(deserialize_with = "ipv4::deserialize_address")]
+    pub dst: String,
+    /// The "type" of the message and its "msg" body.
+    #[serde(flatten)]
+    pub body: Body,
+}
+
+/// The type of a message together with its body.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "msg", rename_all = "lowercase")]
+pub enum Body {
+    /// Opens the session with a neighbor.
+    Handshake(Handshake),
+    /// Keeps the session with a neighbor alive.
+    Keepalive(Empty),
+    /// Closes the session with a neighbor, telling it why.
+    Notification(ErrorReport),
+    /// Asks the receiver to send all its routes again (RFC 2918).
+    #[serde(rename = "route-refresh")]
+    RouteRefresh(Empty),
+    /// Announces a route.
+    Update(Update),
+    /// Withdraws the routes to the given prefixes.
+    Withdraw(Vec<Prefix>),
+    /// Carries a data packet whose payload is never inspected.
+    Data(Value),
+    /// Asks for a copy of the routing table.
+    Dump(Empty),
+    /// Answers a "dump" message with the routing table.
+    Table(Vec<Network>),
+    /// Tells the sender of a data packet that there is no route to its destination.
+    #[serde(rename = "no route")]
+    NoRoute(Empty),
+    /// Asks for, or answers with, the routing table rendered as "show ip bgp" text.
+    Show(Control<String>),
+    /// Asks for, or answers with, the relationship and statistics of every neighbor.
+    Neighbors(Control<Map<String, Value>>),
+    /// Reports a malformed message back to its sender.
+    Error(ErrorReport),
+    /// Asks for the session with a neighbor to be reset.
+    Clear(NeighborCommand),
+    /// Asks for a neighbor to be sent a "route-refresh" message.
+    Refresh(NeighborCommand),
+}
+
+/// The body of a "handshake" message.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[allow(non_snake_case)]
+pub struct Handshake {
+    /// The AS number of the sender, if it announces it.
+    #[serde(
+        default,
+        deserialize_with = "asn::deserialize_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub asn: Option<u32>,
+    /// The hold time proposed by the sender, in seconds. Zero disables the hold timer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holdTime: Option<u64>,
+}
+
+/// The body of an "update" message.
+/// The localpref, selfOrigin and origin are required in the announcements received
+/// from the neighbors, but the router never sends them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[allow(non_snake_case)]
+pub struct Update {
+    #[serde(deserialize_with = "ipv4::deserialize_address")]
+    pub network: String,
+    #[serde(deserialize_with = "ipv4::deserialize_netmask")]
+    pub netmask: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub localpref: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selfOrigin: Option<bool>,
+    #[serde(deserialize_with = "asn::deserialize_path")]
+    pub ASPath: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub communities: Vec<Community>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub largeCommunities: Vec<LargeCommunity>,
+    #[serde(
+        default,
+        deserialize_with = "asn::deserialize_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub otc: Option<u32>,
+}
+
+/// A prefix of a "withdraw" message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Prefix {
+    #[serde(deserialize_with = "ipv4::deserialize_address")]
+    pub network: String,
+    #[serde(deserialize_with = "ipv4::deserialize_netmask")]
+    pub netmask: String,
+}
+
+impl Prefix {
+    /**
+     * This function checks that the prefix holds a valid network and subnet mask.
+     * Return an error describing the invalid address, if any.
+     */
+    pub fn validate(&self) -> Result<(), String> {
+        ipv4::parse(&self.network)?;
+        ipv4::parse_netmask(&self.netmask)?;
+        Ok(())
+    }
+}
+
+/// The body of the messages that carry no information.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Empty {}
+
+/// The body of a control message, which is empty in requests.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Control<T> {
+    Request(Empty),
+    Reply(T),
+}
+
+/// The body of an "error" or "notification" message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorReport {
+    /// The description of the error.
+    pub error: String,
+}
+
+/// The body of the control messages that act on a neighbor, such as "clear" and "refresh".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NeighborCommand {
+    /// The IP address of the neighbor.
+    pub neighbor: String,
+}
+
+#[allow(non_snake_case)]
+impl Update {
+    /**
+     * This function builds the announcement of a route, as it is sent to a neighbor.
+     * net: The route as it is exported to the neighbor.
+     * ASPath: The AS path the neighbor receives.
+     */
+    pub fn announce(net: &Network, ASPath: Vec<u32>) -> Self {
+        Update {
+            network: net.network.clone(),
+            netmask: net.netmask.clone(),
+            localpref: None,
+            selfOrigin: None,
+            ASPath,
+            origin: None,
+            communities: net.communities.clone(),
+            largeCommunities: net.largeCommunities.clone(),
+            otc: net.otc,
+        }
+    }
+
+    /**
+     * This function turns a received announcement into a route of the routing table.
+     * peer: The IP address of the neighbor that sent the announcement.
+     * Return the route, or an error if the announcement lacks a required attribute
+     * or does not hold a valid prefix.
+     */
+    pub fn into_network(self, peer: &str) -> Result<Network, String> {
+        ipv4::parse(&self.network)?;
+        ipv4::parse_netmask(&self.netmask)?;
+        let missing = |field: &str| format!("missing field `{field}`");
+        let mut net = Network::new(
+            peer.to_string(),
+            self.network,
+            self.netmask,
+            self.localpref.ok_or(missing("localpref"))?,
+            self.selfOrigin.ok_or(missing("selfOrigin"))?,
+            self.ASPath,
+            self.origin.ok_or(missing("origin"))?,
+        );
+        net.communities = self.communities;
+        net.largeCommunities = self.largeCommunities;
+        net.otc = self.otc;
+        Ok(net)
+    }
+}
+
+impl Message {
+    // This function creates a message of the given body.
+    pub fn new(src: &str, dst: &str, body: Body) -> Self {
+        Message {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            body,
+        }
+    }
+
+    // This function returns the "type" of the message.
+    pub fn type_name(&self) -> &'static str {
+        match self.body {
+            Body::Handshake(_) => "handshake",
+            Body::Keepalive(_) => "keepalive",
+            Body::Notification(_) => "notification",
+            Body::RouteRefresh(_) => "route-refresh",
+            Body::Update(_) => "update",
+            Body::Withdraw(_) => "withdraw",
+            Body::Data(_) => "data",
+            Body::Dump(_) => "dump",
+            Body::Table(_) => "table",
+            Body::NoRoute(_) => "no route",
+            Body::Show(_) => "show",
+            Body::Neighbors(_) => "neighbors",
+            Body::Error(_) => "error",
+            Body::Clear(_) => "clear",
+            Body::Refresh(_) => "refresh",
+        }
+    }
+
+    // This function serializes the message as it is sent on the wire.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(msg: &str) -> Result<Message, serde_json::Error> {
+        serde_json::from_str(msg)
+    }
+
+    #[test]
+    fn update_with_valid_prefix_parses() {
+        let message = parse(
+            r#"{"src":"10.0.0.2","dst":"10.0.0.1","type":"update","msg":{"network":"12.0.0.0",
+            "netmask":"255.255.0.0","localpref":100,"selfOrigin":true,"ASPath":[1],"origin":"EGP"}}"#,
+        )
+        .unwrap();
+        assert_eq!(message.type_name(), "update");
+    }
+
+    #[test]
+    fn bad_addresses_are_rejected() {
+        assert!(parse(r#"{"src":"10.0.0.2","dst":"garbage","type":"data","msg":{}}"#).is_err());
+        assert!(parse(
+            r#"{"src":"10.0.0.2","dst":"10.0.0.1","type":"update","msg":{"network":"not-an-ip",
+            "netmask":"255.0.0.0","localpref":100,"selfOrigin":true,"ASPath":[1],"origin":"EGP"}}"#
+        )
+        .is_err());
+        assert!(parse(
+            r#"{"src":"10.0.0.2","dst":"10.0.0.1","type":"withdraw",
+            "msg":[{"network":"12.0.0.0","netmask":"255.0.255.0"}]}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,532 @@
+/// This module converts routing tables from and to MRT TABLE_DUMP_V2 files (RFC 6396)
+/// so that they can be exchanged with standard analysis tools.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    community::{Community, LargeCommunity},
+    ipv4::{netmask_digit, netmask_from_digit, to_decimal, to_ipv4},
+    routing_table::{Network, Origin, Table},
+};
+
+// MRT type and subtypes
+const TABLE_DUMP_V2: u16 = 13;
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+
+// Peer type flags of the peer index table
+const PEER_TYPE_IPV6: u8 = 0x01;
+const PEER_TYPE_AS4: u8 = 0x02;
+
+// BGP path attribute flags and type codes
+const ATTR_FLAG_OPTIONAL: u8 = 0x80;
+const ATTR_FLAG_TRANSITIVE: u8 = 0x40;
+const ATTR_FLAG_EXTENDED_LENGTH: u8 = 0x10;
+const ATTR_ORIGIN: u8 = 1;
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_NEXT_HOP: u8 = 3;
+const ATTR_LOCAL_PREF: u8 = 5;
+const ATTR_COMMUNITIES: u8 = 8;
+const ATTR_LARGE_COMMUNITIES: u8 = 32;
+const ATTR_OTC: u8 = 35;
+
+// AS_PATH segment types
+const AS_SET: u8 = 1;
+const AS_SEQUENCE: u8 = 2;
+
+/// The localpref assigned to imported routes that carry no LOCAL_PREF attribute.
+const DEFAULT_LOCAL_PREF: i32 = 100;
+
+/// A cursor over the bytes of an MRT file.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < len {
+            return Err("Data incomplete -> truncated MRT record".to_string());
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/**
+ * This function encodes the given routes as an MRT TABLE_DUMP_V2 file.
+ * The file starts with a PEER_INDEX_TABLE built from the peers of the routes,
+ * followed by one RIB_IPV4_UNICAST record per prefix.
+ * routes: The rows of a routing table.
+ * Return the bytes of the MRT file.
+ */
+pub fn export_table(routes: &[Network]) -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0);
+
+    // Collect the peers, using the first AS of their paths as their AS number
+    let mut peers: Vec<(&str, u32)> = vec![];
+    for net in routes.iter() {
+        if !peers.iter().any(|(peer, _)| *peer == net.peer) {
+            let peer_as = net.ASPath.first().copied().unwrap_or(0);
+            peers.push((&net.peer, peer_as));
+        }
+    }
+
+    let mut output = vec![];
+
+    // Peer index table
+    let mut body = vec![];
+    body.extend_from_slice(&0u32.to_be_bytes()); // Collector BGP ID
+    body.extend_from_slice(&0u16.to_be_bytes()); // View name length
+    body.extend_from_slice(&(peers.len() as u16).to_be_bytes());
+    for (peer, peer_as) in peers.iter() {
+        body.push(PEER_TYPE_AS4);
+        body.extend_from_slice(&to_decimal(peer).to_be_bytes()); // Peer BGP ID
+        body.extend_from_slice(&to_decimal(peer).to_be_bytes()); // Peer IP address
+        body.extend_from_slice(&peer_as.to_be_bytes());
+    }
+    write_record(&mut output, timestamp, PEER_INDEX_TABLE, &body);
+
+    // Group the rows by prefix, keeping the order in which prefixes appear
+    let mut prefixes: Vec<(&str, &str)> = vec![];
+    for net in routes.iter() {
+        if !prefixes.contains(&(&net.network, &net.netmask)) {
+            prefixes.push((&net.network, &net.netmask));
+        }
+    }
+
+    for (sequence, (network, netmask)) in prefixes.iter().enumerate() {
+        let prefix_length = netmask_digit(netmask) as u8;
+        let entries: Vec<&Network> = routes
+            .iter()
+            .filter(|net| net.network == *network && net.netmask == *netmask)
+            .collect();
+
+        let mut body = vec![];
+        body.extend_from_slice(&(sequence as u32).to_be_bytes());
+        body.push(prefix_length);
+        body.extend_from_slice(
+            &to_decimal(network).to_be_bytes()[..(prefix_length as usize).div_ceil(8)],
+        );
+        body.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for net in entries.iter() {
+            let peer_index = peers
+                .iter()
+                .position(|(peer, _)| *peer == net.peer)
+                .unwrap();
+            let attributes = encode_attributes(net);
+            body.extend_from_slice(&(peer_index as u16).to_be_bytes());
+            body.extend_from_slice(&timestamp.to_be_bytes()); // Originated time
+            body.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+            body.extend_from_slice(&attributes);
+        }
+        write_record(&mut output, timestamp, RIB_IPV4_UNICAST, &body);
+    }
+
+    output
+}
+
+/**
+ * This function decodes an MRT TABLE_DUMP_V2 file into a routing table.
+ * Only IPv4 unicast RIB entries are imported; every other record is skipped.
+ * Imported routes are never self originated and default to a localpref of 100
+ * when they carry no LOCAL_PREF attribute.
+ * data: The bytes of the MRT file.
+ * Return the routing table holding the imported routes.
+ */
+pub fn import_table(data: &[u8]) -> Result<Table, String> {
+    let mut table = Table::new();
+    // IP addresses of the peers in the peer index table, None for IPv6 peers
+    let mut peers: Vec<Option<String>> = vec![];
+    let mut reader = Reader::new(data);
+
+    while !reader.is_empty() {
+        let _timestamp = reader.u32()?;
+        let (mrt_type, subtype) = (reader.u16()?, reader.u16()?);
+        let length = reader.u32()? as usize;
+        let mut body = Reader::new(reader.take(length)?);
+
+        if mrt_type != TABLE_DUMP_V2 {
+            continue;
+        }
+
+        match subtype {
+            PEER_INDEX_TABLE => {
+                let _collector_id = body.u32()?;
+                let view_name_length = body.u16()? as usize;
+                body.take(view_name_length)?;
+                peers.clear();
+                for _ in 0..body.u16()? {
+                    let peer_type = body.u8()?;
+                    let _peer_id = body.u32()?;
+                    if peer_type & PEER_TYPE_IPV6 != 0 {
+                        body.take(16)?;
+                        peers.push(None);
+                    } else {
+                        peers.push(Some(to_ipv4(body.u32()?)));
+                    }
+                    body.take(if peer_type & PEER_TYPE_AS4 != 0 { 4 } else { 2 })?;
+                }
+            }
+            RIB_IPV4_UNICAST => {
+                let _sequence = body.u32()?;
+                let prefix_length = body.u8()?;
+                if prefix_length > 32 {
+                    return Err(format!(
+                        "Invalid prefix length {prefix_length} in MRT record"
+                    ));
+                }
+                let mut prefix = [0u8; 4];
+                let prefix_bytes = body.take((prefix_length as usize).div_ceil(8))?;
+                prefix[..prefix_bytes.len()].copy_from_slice(prefix_bytes);
+                let network = to_ipv4(u32::from_be_bytes(prefix));
+                let netmask = netmask_from_digit(prefix_length as i32);
+
+                for _ in 0..body.u16()? {
+                    let peer_index = body.u16()? as usize;
+                    let _originated_time = body.u32()?;
+                    let attributes_length = body.u16()? as usize;
+                    let attributes = body.take(attributes_length)?;
+                    let peer = match peers.get(peer_index) {
+                        Some(Some(peer)) => peer.clone(),
+                        Some(None) => continue,
+                        None => return Err(format!("Unknown peer index {peer_index}")),
+                    };
+                    let mut net = Network::new(
+                        peer,
+                        network.clone(),
+                        netmask.clone(),
+                        DEFAULT_LOCAL_PREF,
+                        false,
+                        vec![],
+                        Origin::UNK,
+                    );
+                    decode_attributes(attributes, &mut net)?;
+
+                    table.update(net);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(table)
+}
+
+/// Appends an MRT TABLE_DUMP_V2 record with the given subtype and body to the output.
+fn write_record(output: &mut Vec<u8>, timestamp: u32, subtype: u16, body: &[u8]) {
+    output.extend_from_slice(&timestamp.to_be_bytes());
+    output.extend_from_slice(&TABLE_DUMP_V2.to_be_bytes());
+    output.extend_from_slice(&subtype.to_be_bytes());
+    output.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    output.extend_from_slice(body);
+}
+
+/// Appends a BGP path attribute to the output, using the extended length when needed.
+fn write_attribute(output: &mut Vec<u8>, flags: u8, type_code: u8, value: &[u8]) {
+    if value.len() > u8::MAX as usize {
+        output.push(flags | ATTR_FLAG_EXTENDED_LENGTH);
+        output.push(type_code);
+        output.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    } else {
+        output.push(flags);
+        output.push(type_code);
+        output.push(value.len() as u8);
+    }
+    output.extend_from_slice(value);
+}
+
+/// Encodes the ORIGIN, AS_PATH, NEXT_HOP, LOCAL_PREF, COMMUNITIES, LARGE_COMMUNITY and OTC attributes of a route.
+fn encode_attributes(net: &Network) -> Vec<u8> {
+    let mut attributes = vec![];
+
+    let origin = match net.origin {
+        Origin::IGP => 0,
+        Origin::EGP => 1,
+        Origin::UNK => 2,
+    };
+    write_attribute(
+        &mut attributes,
+        ATTR_FLAG_TRANSITIVE,
+        ATTR_ORIGIN,
+        &[origin],
+    );
+
+    // AS paths in TABLE_DUMP_V2 always use four-byte AS numbers
+    let mut as_path = vec![];
+    for segment in net.ASPath.chunks(u8::MAX as usize) {
+        as_path.push(AS_SEQUENCE);
+        as_path.push(segment.len() as u8);
+        for asn in segment.iter() {
+            as_path.extend_from_slice(&asn.to_be_bytes());
+        }
+    }
+    write_attribute(
+        &mut attributes,
+        ATTR_FLAG_TRANSITIVE,
+        ATTR_AS_PATH,
+        &as_path,
+    );
+
+    write_attribute(
+        &mut attributes,
+        ATTR_FLAG_TRANSITIVE,
+        ATTR_NEXT_HOP,
+        &to_decimal(&net.peer).to_be_bytes(),
+    );
+    write_attribute(
+        &mut attributes,
+        ATTR_FLAG_TRANSITIVE,
+        ATTR_LOCAL_PREF,
+        &(net.localpref as u32).to_be_bytes(),
+    );
+
+    if !net.communities.is_empty() {
+        let communities: Vec<u8> = net
+            .communities
+            .iter()
+            .flat_map(|community| community.0.to_be_bytes())
+            .collect();
+        write_attribute(
+            &mut attributes,
+            ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE,
+            ATTR_COMMUNITIES,
+            &communities,
+        );
+    }
+
+    if !net.largeCommunities.is_empty() {
+        let large_communities: Vec<u8> = net
+            .largeCommunities
+            .iter()
+            .flat_map(|community| {
+                [community.asn, community.data1, community.data2]
+                    .into_iter()
+                    .flat_map(|part| part.to_be_bytes())
+            })
+            .collect();
+        write_attribute(
+            &mut attributes,
+            ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE,
+            ATTR_LARGE_COMMUNITIES,
+            &large_communities,
+        );
+    }
+
+    if let Some(otc) = net.otc {
+        write_attribute(
+            &mut attributes,
+            ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE,
+            ATTR_OTC,
+            &otc.to_be_bytes(),
+        );
+    }
+
+    attributes
+}
+
+/// Decodes the localpref, AS path, origin, (large) communities and OTC attribute of a route
+/// from the BGP path attributes of a RIB entry.
+fn decode_attributes(data: &[u8], net: &mut Network) -> Result<(), String> {
+    let mut reader = Reader::new(data);
+
+    while !reader.is_empty() {
+        let flags = reader.u8()?;
+        let type_code = reader.u8()?;
+        let length = if flags & ATTR_FLAG_EXTENDED_LENGTH != 0 {
+            reader.u16()? as usize
+        } else {
+            reader.u8()? as usize
+        };
+        let mut value = Reader::new(reader.take(length)?);
+
+        match type_code {
+            ATTR_ORIGIN => {
+                net.origin = match value.u8()? {
+                    0 => Origin::IGP,
+                    1 => Origin::EGP,
+                    _ => Origin::UNK,
+                };
+            }
+            ATTR_AS_PATH => {
+                while !value.is_empty() {
+                    let segment_type = value.u8()?;
+                    let count = value.u8()?;
+                    for i in 0..count {
+                        let asn = value.u32()?;
+                        match segment_type {
+                            AS_SEQUENCE => net.ASPath.push(asn),
+                            // A set counts as a single AS in the path length (RFC 4271),
+                            // so only its first member is kept
+                            AS_SET if i == 0 => net.ASPath.push(asn),
+                            // Confederation segments are not part of the path length
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            ATTR_LOCAL_PREF => {
+                net.localpref = value.u32()? as i32;
+            }
+            ATTR_COMMUNITIES => {
+                while !value.is_empty() {
+                    net.communities.push(Community(value.u32()?));
+                }
+                net.communities.sort();
+                net.communities.dedup();
+            }
+            ATTR_LARGE_COMMUNITIES => {
+                while !value.is_empty() {
+                    net.largeCommunities.push(LargeCommunity {
+                        asn: value.u32()?,
+                        data1: value.u32()?,
+                        data2: value.u32()?,
+                    });
+                }
+                net.largeCommunities.sort();
+                net.largeCommunities.dedup();
+            }
+            ATTR_OTC => {
+                net.otc = Some(value.u32()?);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(peer: &str, network: &str, netmask: &str, path: Vec<u32>) -> Network {
+        Network::new(
+            peer.to_string(),
+            network.to_string(),
+            netmask.to_string(),
+            150,
+            false,
+            path,
+            Origin::IGP,
+        )
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let mut tagged = route(
+            "192.168.0.2",
+            "10.0.0.0",
+            "255.255.0.0",
+            vec![1, 4_200_000_000],
+        );
+        tagged.communities = vec![Community(0x0001_0064), Community(0xFFFF_FF01)];
+        tagged.largeCommunities = vec![LargeCommunity {
+            asn: 4_200_000_000,
+            data1: 1,
+            data2: 2,
+        }];
+        tagged.otc = Some(65001);
+        let plain = route("172.168.0.2", "12.0.0.0", "255.0.0.0", vec![2]);
+
+        let table = import_table(&export_table(&[tagged, plain])).unwrap();
+        let routes = table.get_table();
+        assert_eq!(routes.len(), 2);
+
+        let imported = &routes[0];
+        assert_eq!(imported.peer, "192.168.0.2");
+        assert_eq!(
+            (imported.network.as_str(), imported.netmask.as_str()),
+            ("10.0.0.0", "255.255.0.0")
+        );
+        assert_eq!(imported.localpref, 150);
+        assert_eq!(imported.ASPath, vec![1, 4_200_000_000]);
+        assert_eq!(imported.origin, Origin::IGP);
+        assert_eq!(
+            imported.communities,
+            vec![Community(0x0001_0064), Community(0xFFFF_FF01)]
+        );
+        assert_eq!(
+            imported.largeCommunities,
+            vec![LargeCommunity {
+                asn: 4_200_000_000,
+                data1: 1,
+                data2: 2,
+            }]
+        );
+        assert_eq!(imported.otc, Some(65001));
+
+        let imported = &routes[1];
+        assert_eq!(imported.peer, "172.168.0.2");
+        assert_eq!(imported.ASPath, vec![2]);
+        assert!(imported.communities.is_empty() && imported.largeCommunities.is_empty());
+        assert_eq!(imported.otc, None);
+    }
+
+    #[test]
+    fn truncated_records_are_rejected() {
+        let mut tagged = route("192.168.0.2", "10.0.0.0", "255.255.0.0", vec![1]);
+        tagged.communities = vec![Community(0x0001_0064)];
+        let data = export_table(&[tagged]);
+        // The peer index table is a record header, the collector ID, view name length,
+        // peer count and a single peer, after which a truncated file is still whole
+        let first_record = 12 + 4 + 2 + 2 + 13;
+
+        for len in 1..data.len() {
+            let result = import_table(&data[..len]);
+            if len == first_record {
+                assert!(result.unwrap().get_table().is_empty());
+            } else {
+                assert!(result.is_err(), "accepted {len} of {} bytes", data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn as_set_counts_as_one_as() {
+        let mut as_path = vec![AS_SEQUENCE, 2];
+        for asn in [1u32, 2] {
+            as_path.extend_from_slice(&asn.to_be_bytes());
+        }
+        as_path.extend_from_slice(&[AS_SET, 3]);
+        for asn in [30u32, 31, 32] {
+            as_path.extend_from_slice(&asn.to_be_bytes());
+        }
+        let mut attributes = vec![];
+        write_attribute(
+            &mut attributes,
+            ATTR_FLAG_TRANSITIVE,
+            ATTR_AS_PATH,
+            &as_path,
+        );
+
+        let mut net = route("192.168.0.2", "10.0.0.0", "255.255.0.0", vec![]);
+        decode_attributes(&attributes, &mut net).unwrap();
+        assert_eq!(net.ASPath, vec![1, 2, 30]);
+    }
+}
@@ -0,0 +1,523 @@
+/// This module contains the routing policy building blocks
+/// that can be configured per neighbor.
+use regex::Regex;
+use serde::Deserialize;
+use std::net::Ipv4Addr;
+
+use crate::{
+    community::{Community, LargeCommunity},
+    config::RouterConfig,
+    ipv4::{check_match, netmask_digit, netmask_from_digit, to_decimal, to_ipv4},
+    router::NeighborType,
+    routing_table::{Network, Origin},
+};
+
+/// Whether a matching policy entry permits or denies a route.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Permit,
+    Deny,
+}
+
+/// A single entry of an AS-path access list.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawAsPathEntry")]
+pub struct AsPathEntry {
+    action: Action,
+    regex: Regex,
+}
+
+/// An AS-path access list entry as it is written in the configuration.
+#[derive(Deserialize)]
+struct RawAsPathEntry {
+    action: Action,
+    regex: String,
+}
+
+impl TryFrom<RawAsPathEntry> for AsPathEntry {
+    type Error = String;
+
+    fn try_from(raw: RawAsPathEntry) -> Result<Self, Self::Error> {
+        Ok(AsPathEntry {
+            action: raw.action,
+            regex: as_path_regex(&raw.regex)?,
+        })
+    }
+}
+
+/**
+ * An ordered AS-path access list, evaluated like Cisco's "ip as-path access-list".
+ * The first entry whose regular expression matches the path decides,
+ * and a path that matches no entry is denied.
+ * An empty list permits every path.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AsPathAccessList {
+    entries: Vec<AsPathEntry>,
+}
+
+impl AsPathAccessList {
+    /**
+     * This function checks if the access list permits the given AS path.
+     * The path is matched as its AS numbers separated by spaces, e.g. "174 3356 65001".
+     * as_path: The AS path to check.
+     * Return true if the path is permitted. Otherwise, false.
+     */
+    pub fn permits(&self, as_path: &[u32]) -> bool {
+        if self.entries.is_empty() {
+            return true;
+        }
+
+        let path = as_path_string(as_path);
+        self.entries
+            .iter()
+            .find(|entry| entry.regex.is_match(&path))
+            .map(|entry| entry.action == Action::Permit)
+            .unwrap_or(false)
+    }
+}
+
+/// A single entry of a community list.
+/// The entry matches routes that carry all of its communities and large communities,
+/// so an entry without any community matches every route.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommunityEntry {
+    action: Action,
+    #[serde(default)]
+    communities: Vec<Community>,
+    #[serde(default)]
+    large_communities: Vec<LargeCommunity>,
+}
+
+impl CommunityEntry {
+    // This function checks if the route carries all the communities of the entry.
+    fn matches(&self, net: &Network) -> bool {
+        self.communities
+            .iter()
+            .all(|community| net.communities.contains(community))
+            && self
+                .large_communities
+                .iter()
+                .all(|community| net.largeCommunities.contains(community))
+    }
+}
+
+/**
+ * An ordered community list, evaluated like Cisco's "ip community-list"
+ * and "ip large-community-list".
+ * The first matching entry decides, and a route that matches no entry is denied.
+ * An empty list permits every route.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct CommunityList {
+    entries: Vec<CommunityEntry>,
+}
+
+impl CommunityList {
+    /**
+     * This function checks if the community list permits the given route.
+     * net: The route to check.
+     * Return true if the route is permitted. Otherwise, false.
+     */
+    pub fn permits(&self, net: &Network) -> bool {
+        if self.entries.is_empty() {
+            return true;
+        }
+
+        self.entries
+            .iter()
+            .find(|entry| entry.matches(net))
+            .map(|entry| entry.action == Action::Permit)
+            .unwrap_or(false)
+    }
+}
+
+/// A single entry of a prefix list.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawPrefixEntry")]
+pub struct PrefixEntry {
+    action: Action,
+    network: String,
+    netmask: String,
+    /// The shortest prefix length matched by the entry.
+    ge: i32,
+    /// The longest prefix length matched by the entry.
+    le: i32,
+}
+
+/// A prefix list entry as it is written in the configuration.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPrefixEntry {
+    action: Action,
+    prefix: String,
+    ge: Option<i32>,
+    le: Option<i32>,
+}
+
+impl TryFrom<RawPrefixEntry> for PrefixEntry {
+    type Error = String;
+
+    fn try_from(raw: RawPrefixEntry) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid prefix {}", raw.prefix);
+        let (network, length) = raw.prefix.split_once('/').ok_or_else(invalid)?;
+        let network: Ipv4Addr = network.parse().map_err(|_| invalid())?;
+        let length: i32 = length
+            .parse()
+            .ok()
+            .filter(|len| (0..=32).contains(len))
+            .ok_or_else(invalid)?;
+        // Like in Cisco's syntax, a prefix without "ge" and "le" only matches its own length
+        let ge = raw.ge.unwrap_or(length);
+        let le = raw.le.unwrap_or(if raw.ge.is_some() { 32 } else { length });
+        if ge < length || le < ge || le > 32 {
+            return Err(format!("Invalid ge/le for prefix {}", raw.prefix));
+        }
+        let netmask = netmask_from_digit(length);
+
+        Ok(PrefixEntry {
+            action: raw.action,
+            network: to_ipv4(u32::from(network) & to_decimal(&netmask)),
+            netmask,
+            ge,
+            le,
+        })
+    }
+}
+
+impl PrefixEntry {
+    // This function checks if the prefix of the route is covered by the entry with an allowed length.
+    fn matches(&self, net: &Network) -> bool {
+        let length = netmask_digit(&net.netmask);
+        (self.ge..=self.le).contains(&length)
+            && check_match(&self.network, &self.netmask, &net.network)
+    }
+}
+
+/**
+ * An ordered prefix list, evaluated like Cisco's "ip prefix-list".
+ * The first matching entry decides, and a route that matches no entry is denied.
+ * An empty list permits every route.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct PrefixList {
+    entries: Vec<PrefixEntry>,
+}
+
+impl PrefixList {
+    /**
+     * This function checks if the prefix list permits the prefix of the given route.
+     * net: The route to check.
+     * Return true if the route is permitted. Otherwise, false.
+     */
+    pub fn permits(&self, net: &Network) -> bool {
+        if self.entries.is_empty() {
+            return true;
+        }
+
+        self.entries
+            .iter()
+            .find(|entry| entry.matches(net))
+            .map(|entry| entry.action == Action::Permit)
+            .unwrap_or(false)
+    }
+}
+
+/// The conditions of a route-map entry. Every given condition must hold for the entry to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteMapMatch {
+    /// The name of a prefix list that must permit the route.
+    pub prefix_list: Option<String>,
+    /// The name of an AS-path access list that must permit the path of the route.
+    pub as_path: Option<String>,
+    /// The name of a community list that must permit the route.
+    pub community: Option<String>,
+    /// The origin of the route.
+    pub origin: Option<Origin>,
+    /// The relationship with the neighbor the route was learned from.
+    pub relation: Option<NeighborType>,
+}
+
+/// The changes a permitting route-map entry applies to the route.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteMapSet {
+    pub localpref: Option<i32>,
+    /// Prepends our AS number this many times to the path.
+    pub prepend: usize,
+    pub add_communities: Vec<Community>,
+    pub remove_communities: Vec<Community>,
+    pub add_large_communities: Vec<LargeCommunity>,
+    pub remove_large_communities: Vec<LargeCommunity>,
+}
+
+/// A single entry of a route-map.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteMapEntry {
+    pub action: Action,
+    #[serde(default, rename = "match")]
+    pub conditions: RouteMapMatch,
+    #[serde(default)]
+    pub set: RouteMapSet,
+}
+
+/**
+ * An ordered route-map, evaluated like Cisco's "route-map".
+ * The first entry whose conditions all hold decides: a "permit" entry applies
+ * its changes to the route and accepts it, a "deny" entry rejects it.
+ * A route that matches no entry is rejected.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct RouteMap {
+    pub entries: Vec<RouteMapEntry>,
+}
+
+impl RouteMap {
+    /**
+     * This function applies the route-map to the given route.
+     * net: The route, which gets changed by the permitting entry.
+     * source: The relationship with the neighbor the route was learned from.
+     * asn: Our AS number, used for prepending.
+     * config: The configuration holding the lists referenced by the route-map.
+     * Return true if the route is permitted. Otherwise, false.
+     */
+    pub fn apply(
+        &self,
+        net: &mut Network,
+        source: &NeighborType,
+        asn: u32,
+        config: &RouterConfig,
+    ) -> bool {
+        let entry = match self
+            .entries
+            .iter()
+            .find(|entry| entry.matches(net, source, config))
+        {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if entry.action == Action::Deny {
+            return false;
+        }
+
+        let set = &entry.set;
+        if let Some(localpref) = set.localpref {
+            net.localpref = localpref;
+        }
+        for _ in 0..set.prepend {
+            net.ASPath.insert(0, asn);
+        }
+        net.communities
+            .retain(|community| !set.remove_communities.contains(community));
+        net.communities.extend(set.add_communities.iter());
+        net.communities.sort();
+        net.communities.dedup();
+        net.largeCommunities
+            .retain(|community| !set.remove_large_communities.contains(community));
+        net.largeCommunities
+            .extend(set.add_large_communities.iter());
+        net.largeCommunities.sort();
+        net.largeCommunities.dedup();
+
+        true
+    }
+}
+
+impl RouteMapEntry {
+    // This function checks if every condition of the entry holds for the route.
+    fn matches(&self, net: &Network, source: &NeighborType, config: &RouterConfig) -> bool {
+        let conditions = &self.conditions;
+        if let Some(name) = &conditions.prefix_list {
+            if !config.prefix_lists[name].permits(net) {
+                return false;
+            }
+        }
+        if let Some(name) = &conditions.as_path {
+            if !config.as_path_lists[name].permits(&net.ASPath) {
+                return false;
+            }
+        }
+        if let Some(name) = &conditions.community {
+            if !config.community_lists[name].permits(net) {
+                return false;
+            }
+        }
+        if let Some(origin) = &conditions.origin {
+            if *origin != net.origin {
+                return false;
+            }
+        }
+        if let Some(relation) = &conditions.relation {
+            if relation != source {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Returns the AS path as its AS numbers separated by spaces.
+pub fn as_path_string(as_path: &[u32]) -> String {
+    let path: Vec<String> = as_path.iter().map(|asn| asn.to_string()).collect();
+    path.join(" ")
+}
+
+/**
+ * This function compiles an AS-path regular expression.
+ * Like in Cisco's syntax, '_' matches the start or end of the path
+ * or the space between two AS numbers.
+ * i.g. "_65001$" matches every path originated by AS 65001.
+ * regex: The AS-path regular expression.
+ * Return the compiled regular expression.
+ */
+pub fn as_path_regex(regex: &str) -> Result<Regex, String> {
+    Regex::new(&regex.replace('_', "(?:^| |$)"))
+        .map_err(|e| format!("{e} -> invalid AS-path regular expression {regex}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn route(network: &str, netmask: &str, path: Vec<u32>) -> Network {
+        Network::new(
+            "192.168.0.2".to_string(),
+            network.to_string(),
+            netmask.to_string(),
+            100,
+            false,
+            path,
+            Origin::EGP,
+        )
+    }
+
+    fn prefix_list(entries: serde_json::Value) -> PrefixList {
+        serde_json::from_value(entries).unwrap()
+    }
+
+    #[test]
+    fn prefix_list_without_ge_le_matches_exact_length() {
+        let list = prefix_list(json!([{"action": "permit", "prefix": "10.0.0.0/8"}]));
+        assert!(list.permits(&route("10.0.0.0", "255.0.0.0", vec![1])));
+        assert!(!list.permits(&route("10.1.0.0", "255.255.0.0", vec![1])));
+        assert!(!list.permits(&route("11.0.0.0", "255.0.0.0", vec![1])));
+    }
+
+    #[test]
+    fn prefix_list_ge_le_bound_the_length() {
+        let list = prefix_list(json!([
+            {"action": "deny", "prefix": "10.0.0.0/8", "ge": 25},
+            {"action": "permit", "prefix": "10.0.0.0/8", "ge": 16, "le": 24},
+        ]));
+        assert!(!list.permits(&route("10.0.0.0", "255.0.0.0", vec![1])));
+        assert!(list.permits(&route("10.1.0.0", "255.255.0.0", vec![1])));
+        assert!(list.permits(&route("10.1.2.0", "255.255.255.0", vec![1])));
+        assert!(!list.permits(&route("10.1.2.128", "255.255.255.128", vec![1])));
+
+        // "le" alone starts at the length of the prefix
+        let list = prefix_list(json!([{"action": "permit", "prefix": "10.0.0.0/8", "le": 16}]));
+        assert!(list.permits(&route("10.0.0.0", "255.0.0.0", vec![1])));
+        assert!(list.permits(&route("10.1.0.0", "255.255.0.0", vec![1])));
+        assert!(!list.permits(&route("10.1.2.0", "255.255.255.0", vec![1])));
+    }
+
+    #[test]
+    fn prefix_list_rejects_bad_entries() {
+        for entry in [
+            json!({"action": "permit", "prefix": "10.0.0.0/33"}),
+            json!({"action": "permit", "prefix": "10.0.0/8"}),
+            json!({"action": "permit", "prefix": "10.0.0.0/16", "ge": 8}),
+            json!({"action": "permit", "prefix": "10.0.0.0/8", "ge": 24, "le": 16}),
+            json!({"action": "permit", "prefix": "10.0.0.0/8", "le": 33}),
+        ] {
+            assert!(serde_json::from_value::<PrefixList>(json!([entry])).is_err());
+        }
+    }
+
+    #[test]
+    fn as_path_underscore_matches_boundaries() {
+        let regex = as_path_regex("_65001$").unwrap();
+        assert!(regex.is_match("174 65001"));
+        assert!(regex.is_match("65001"));
+        assert!(!regex.is_match("174 165001"));
+
+        let regex = as_path_regex("_174_").unwrap();
+        assert!(regex.is_match("174"));
+        assert!(regex.is_match("3356 174 65001"));
+        assert!(!regex.is_match("1174 65001"));
+        assert!(!regex.is_match("3356 1745"));
+
+        assert!(as_path_regex("(").is_err());
+    }
+
+    #[test]
+    fn as_path_list_first_match_decides() {
+        let list: AsPathAccessList = serde_json::from_value(json!([
+            {"action": "deny", "regex": "_666_"},
+            {"action": "permit", "regex": ".*"},
+        ]))
+        .unwrap();
+        assert!(!list.permits(&[1, 666, 2]));
+        assert!(list.permits(&[1, 6666, 2]));
+        assert!(AsPathAccessList::default().permits(&[666]));
+    }
+
+    #[test]
+    fn route_map_applies_first_matching_entry() {
+        let config: RouterConfig = serde_json::from_value(json!({
+            "prefix_lists": {
+                "customers": [{"action": "permit", "prefix": "10.0.0.0/8", "le": 24}],
+            },
+            "as_path_lists": {
+                "bogons": [{"action": "permit", "regex": "_666_"}],
+            },
+            "route_maps": {
+                "import": [
+                    {"action": "deny", "match": {"as_path": "bogons"}},
+                    {
+                        "action": "permit",
+                        "match": {"prefix_list": "customers", "relation": "cust"},
+                        "set": {
+                            "localpref": 200,
+                            "prepend": 2,
+                            "add_communities": ["65000:1"],
+                            "remove_communities": ["65000:2"],
+                        },
+                    },
+                ],
+            },
+        }))
+        .unwrap();
+        let route_map = &config.route_maps["import"];
+
+        let mut net = route("10.1.0.0", "255.255.0.0", vec![1]);
+        net.communities = vec!["65000:2".parse().unwrap(), "65000:3".parse().unwrap()];
+        assert!(route_map.apply(&mut net, &NeighborType::Cust, 7, &config));
+        assert_eq!(net.localpref, 200);
+        assert_eq!(net.ASPath, vec![7, 7, 1]);
+        assert_eq!(
+            net.communities,
+            vec!["65000:1".parse().unwrap(), "65000:3".parse().unwrap()]
+        );
+
+        // A deny entry rejects the route before any later entry changes it
+        let mut net = route("10.1.0.0", "255.255.0.0", vec![1, 666]);
+        assert!(!route_map.apply(&mut net, &NeighborType::Cust, 7, &config));
+        assert_eq!(net.localpref, 100);
+
+        // A route that matches no entry is rejected
+        let mut net = route("10.1.0.0", "255.255.0.0", vec![1]);
+        assert!(!route_map.apply(&mut net, &NeighborType::Peer, 7, &config));
+        let mut net = route("11.0.0.0", "255.0.0.0", vec![1]);
+        assert!(!route_map.apply(&mut net, &NeighborType::Cust, 7, &config));
+    }
+}
@@ -0,0 +1,167 @@
+/// This module records the control messages received by the router as JSON lines
+/// and replays such recordings into a fresh routing table.
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::message::{Body, Message};
+use crate::routing_table::{Network, Table};
+
+/// A single recorded message.
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    /// Seconds since the Unix epoch at which the message was received.
+    timestamp: f64,
+    /// The IP address of the neighbor that sent the message.
+    neighbor: String,
+    /// The message as it was received.
+    message: Message,
+    /// The route installed for an "update" message, after the import policies of the router.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route: Option<Network>,
+    /// Whether the route of an "update" message was rejected by the import policies.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    rejected: bool,
+}
+
+/// Appends every received "update" and "withdraw" message to a JSON-lines file.
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    /// Opens the recording at the given path, appending to it if it already exists.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{e} -> failed to open recording {path}"))?;
+        Ok(Recorder { file })
+    }
+
+    /**
+     * This function appends a message received from the given neighbor to the recording.
+     * Messages other than "update" and "withdraw" are ignored.
+     * neighbor: The IP address of the neighbor that sent the message.
+     * message: The received message.
+     * route: The route installed for an "update" message, or None if the import policies rejected it.
+     */
+    pub fn record(
+        &self,
+        neighbor: &str,
+        message: &Message,
+        route: Option<&Network>,
+    ) -> Result<(), String> {
+        if !matches!(message.body, Body::Update(_) | Body::Withdraw(_)) {
+            return Ok(());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let record = Record {
+            timestamp,
+            neighbor: neighbor.to_string(),
+            message: message.clone(),
+            route: route.cloned(),
+            rejected: matches!(message.body, Body::Update(_)) && route.is_none(),
+        };
+
+        // Each record is written with a single call so that lines never interleave
+        let line = format!("{}\n", json!(record));
+        (&self.file)
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("{e} -> failed to write the recording"))
+    }
+}
+
+/**
+ * This function replays a recording into a fresh routing table.
+ * The messages are applied in the order in which they were recorded. Updates install
+ * the route recorded after the import policies of the router, so that the final state
+ * is the one of the router, and rejected updates withdraw the earlier route of the neighbor
+ * to the same prefix, if any.
+ * Recordings without routes fall back to installing the received routes.
+ * path: The path of the recording.
+ * Return the final state of the routing table.
+ */
+pub fn replay(path: &str) -> Result<Table, String> {
+    let file = File::open(path).map_err(|e| format!("{e} -> failed to open recording {path}"))?;
+    let mut table = Table::new();
+    // The prefixes every neighbor currently announces, as the router tracks them
+    let mut announced: HashMap<String, HashSet<(String, String)>> = HashMap::new();
+
+    for (ind, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{e} -> failed to read recording {path}"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line)
+            .map_err(|e| format!("{e} -> failed to parse record on line {}", ind + 1))?;
+        let neighbor_prefixes = announced.entry(record.neighbor.clone()).or_default();
+        match record.message.body {
+            Body::Update(update) if record.rejected => {
+                // The rejected route still replaces whatever this neighbor announced before
+                let key = (update.network.clone(), update.netmask.clone());
+                if neighbor_prefixes.remove(&key) {
+                    table.withdraw(&update.network, &update.netmask, &record.neighbor);
+                }
+            }
+            Body::Update(update) => {
+                neighbor_prefixes.insert((update.network.clone(), update.netmask.clone()));
+                let net = match record.route {
+                    Some(net) => net,
+                    // Include peer in the route for table row update
+                    None => update
+                        .into_network(&record.neighbor)
+                        .map_err(|e| format!("{e} -> invalid update on line {}", ind + 1))?,
+                };
+                table.update(net);
+            }
+            Body::Withdraw(prefixes) => {
+                for prefix in prefixes.iter() {
+                    prefix
+                        .validate()
+                        .map_err(|e| format!("{e} -> invalid withdraw on line {}", ind + 1))?;
+                    neighbor_prefixes.remove(&(prefix.network.clone(), prefix.netmask.clone()));
+                    table.withdraw(&prefix.network, &prefix.netmask, &record.neighbor);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_update_keeps_covering_route() {
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+        let update = |network: &str, netmask: &str, path: &str| {
+            format!(
+                r#"{{"timestamp":1,"neighbor":"192.168.0.2","message":{{"src":"192.168.0.2","dst":"192.168.0.1","type":"update","msg":{{"network":"{network}","netmask":"{netmask}","localpref":100,"ASPath":{path},"origin":"EGP","selfOrigin":true}}}}"#
+            )
+        };
+        let lines = [
+            update("10.0.0.0", "255.255.0.0", "[1]") + "}",
+            update("10.0.1.0", "255.255.255.0", "[1,7]") + r#","rejected":true}"#,
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let table = replay(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let table = table.unwrap();
+        assert_eq!(table.get_table().len(), 1);
+        assert_eq!(table.get_table()[0].netmask, "255.255.0.0");
+    }
+}
@@ -0,0 +1,84 @@
+/// This module implements route leak prevention and detection with
+/// BGP roles and the Only-To-Customer (OTC) attribute (RFC 9234).
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::router::NeighborType;
+
+/// The role of our AS on the session with a neighbor.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// We provide transit to the neighbor, which is our customer.
+    Provider,
+    /// We are a customer of the neighbor, which is our provider.
+    Customer,
+    /// We are a route server and the neighbor is one of its clients.
+    Rs,
+    /// We are a client of the neighbor, which is a route server.
+    RsClient,
+    /// The neighbor is our peer.
+    Peer,
+}
+
+/// What the router does with the routes detected as route leaks.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, ValueEnum)]
+pub enum LeakPolicy {
+    /// Neither set nor check the OTC attribute, only propagating it as received.
+    Off,
+    /// Reject leaked routes.
+    Drop,
+    /// Install leaked routes but flag them as leaks.
+    Flag,
+}
+
+impl Role {
+    // This function returns our role on the session with a neighbor of the given relationship.
+    pub fn from_relation(relation: &NeighborType) -> Self {
+        match relation {
+            NeighborType::Cust => Role::Provider,
+            NeighborType::Prov => Role::Customer,
+            NeighborType::Peer => Role::Peer,
+        }
+    }
+
+    /**
+     * This function applies the ingress procedure to a route received on a session with this role.
+     * otc: The OTC attribute of the route, which gets set if the route must carry one.
+     * remote_asn: The AS number of the neighbor, if known.
+     * Return true if the route is a route leak. Otherwise, false.
+     */
+    pub fn check_ingress(&self, otc: &mut Option<u32>, remote_asn: Option<u32>) -> bool {
+        match (self, *otc) {
+            // Routes received from customers and RS clients must never carry the OTC attribute
+            (Role::Provider | Role::Rs, Some(_)) => true,
+            // Peers may only send routes whose OTC attribute they set themselves
+            (Role::Peer, Some(otc)) => remote_asn.is_some_and(|asn| asn != otc),
+            // Routes received from providers, peers and route servers are only sent to customers
+            (Role::Customer | Role::Peer | Role::RsClient, None) => {
+                *otc = remote_asn;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /**
+     * This function applies the egress procedure to a route sent on a session with this role.
+     * otc: The OTC attribute of the route, which gets set if the route must carry one.
+     * local_asn: Our AS number.
+     * Return true if the route may be sent. Otherwise, false.
+     */
+    pub fn permits_egress(&self, otc: &mut Option<u32>, local_asn: u32) -> bool {
+        match (self, *otc) {
+            // Routes carrying the OTC attribute are never sent to providers, peers and route servers
+            (Role::Customer | Role::Peer | Role::RsClient, Some(_)) => false,
+            // Routes sent to customers, peers and RS clients must only go down to customers from there
+            (Role::Provider | Role::Peer | Role::Rs, None) => {
+                *otc = Some(local_asn);
+                true
+            }
+            _ => true,
+        }
+    }
+}
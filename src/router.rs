@@ -1,26 +1,48 @@
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::ErrorKind;
 use std::net::UdpSocket;
-use std::sync::Mutex;
+use std::os::fd::AsRawFd;
+use std::time::Instant;
 
+use crate::adj_rib_out::{AdjRibOut, AdvertiseMode};
+use crate::asn::AsNotation;
+use crate::community::Community;
+use crate::config::RouterConfig;
+use crate::error::RouterError;
+use crate::ipv4;
+use crate::message::{
+    Body, Control, Empty, ErrorReport, Handshake, Message, NeighborCommand, Prefix, Update,
+};
+use crate::mrt::export_table;
+use crate::recorder::Recorder;
+use crate::role::{LeakPolicy, Role};
 use crate::routing_table::{Network, Table};
+use crate::rpki::{RoaSet, RpkiPolicy, RpkiState};
+use crate::session::{Session, SessionState};
+use crate::show::render_table;
 
 /// Represents the type of relationship with a neighbor.
-#[derive(Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum NeighborType {
     /// Indicates a peer relationship.
+    #[serde(rename = "peer")]
     Peer,
     /// Indicates a customer relationship.
+    #[serde(rename = "cust")]
     Cust,
     /// Indicates a provider relationship.
+    #[serde(rename = "prov")]
     Prov,
 }
 
 /// A router that maintains connections, port mappings, and relationships with neighbors.
 #[derive(Debug)]
 pub struct Router {
-    asn: u8,
+    asn: u32,
     /// Maps neighbor IP addresses to their respective UDP sockets for communication.
     /// This allows the router to send and receive packets to and from each neighbor.
     sockets: HashMap<String, UdpSocket>,
@@ -32,54 +54,144 @@ pub struct Router {
     /// The relationship can be one of Peer, Customer (Cust), or Provider (Prov),
     /// and affects routing decisions and policy.
     relations: HashMap<String, NeighborType>,
+    /// The IP addresses of the neighbors, in the order they were added.
+    peers: Vec<String>,
+    /// The routing table, which the router updates with the routes of the neighbors
+    /// and uses to make routing decisions.
+    table: Table,
+    /// Path of the MRT TABLE_DUMP_V2 file written on every "dump" message, if any.
+    mrt_dump: Option<String>,
+    /// Records every received "update" and "withdraw" message, if enabled.
+    recorder: Option<Recorder>,
+    /// The ROA set used to validate route origins, if RPKI validation is enabled.
+    roas: Option<RoaSet>,
+    /// What to do with routes that are RPKI invalid.
+    rpki_policy: RpkiPolicy,
+    /// How many times our own AS may appear in the path of an accepted route.
+    allowas_in: usize,
+    /// Maps neighbor IP addresses to the statistics kept about them.
+    stats: HashMap<String, NeighborStats>,
+    /// The policies configured per neighbor.
+    config: RouterConfig,
+    /// How AS numbers are written in "show" replies.
+    as_notation: AsNotation,
+    /// Maps neighbor IP addresses to the AS numbers they sent in their handshake.
+    learned_asns: HashMap<String, u32>,
+    /// What to do with the routes detected as route leaks.
+    leak_policy: LeakPolicy,
+    /// Whether malformed messages are reported back to their sender with an "error" message.
+    notify_errors: bool,
+    /// The hold time proposed to the neighbors, in seconds. Zero disables the hold timer.
+    hold_time: u64,
+    /// Maps neighbor IP addresses to their BGP sessions.
+    sessions: HashMap<String, Session>,
+    /// Maps neighbor IP addresses to the prefixes they currently announce to us.
+    announced: HashMap<String, BTreeSet<(String, String)>>,
+    /// Which announcements are sent to the neighbors.
+    advertise_mode: AdvertiseMode,
+    /// The routes last announced to every neighbor.
+    adj_rib_out: AdjRibOut,
 }
 
-lazy_static! {
-    // Create a globally accessible `Router` instance wrapped in a `Mutex` for thread safe mutable access.
-    pub static ref GLOBAL_ROUTER: Mutex<Router> = Mutex::new(Router {
-        asn: 0,
-        sockets: HashMap::new(),
-        ports: HashMap::new(),
-        relations: HashMap::new(),
-    });
-    // Create neighbor vector for storing all the neighbors
-    pub static ref GLOBAL_PEER: Mutex<Vec<String>> = Mutex::new(vec![]);
-
-    // Create a globally accessible `Table` instance wrapped in a `Mutex` for thread safe mutable access.
-    // During the BGP process, the router will 'update' this table with new routes and use it to make routing decisions.
-    pub static ref GLOBAL_TABLE: Mutex<Table> = Mutex::new(Table::new());
-}
-
-/// A message that can be sent between routers.
-#[derive(Serialize, Deserialize, Debug)]
-struct Message {
-    src: String,
-    dst: String,
-    r#type: String,
-    msg: Value,
+/// Statistics kept about a neighbor.
+#[derive(Serialize, Debug, Default)]
+struct NeighborStats {
+    /// Number of updates rejected because their path already contained our AS.
+    loops_rejected: u64,
+    /// Number of updates rejected because their path did not start with the AS of the neighbor.
+    first_as_rejected: u64,
+    /// Number of received routes detected as route leaks by their OTC attribute.
+    leaks_detected: u64,
+    /// Number of updates rejected by the import AS-path filter.
+    import_filtered: u64,
+    /// Number of updates rejected because they were RPKI invalid.
+    rpki_dropped: u64,
+    /// Number of messages that could not be handled because they were malformed.
+    malformed: u64,
 }
 
 impl Router {
-    pub fn assign_asn(asn: u8) -> Result<(), String> {
-        let mut router = GLOBAL_ROUTER
-            .lock()
-            .map_err(|e| format!("Failed to lock router: {}", e))?;
+    // Creates a new Router instance with the given AS number, without any neighbor
+    pub fn new(asn: u32) -> Self {
+        Router {
+            asn,
+            sockets: HashMap::new(),
+            ports: HashMap::new(),
+            relations: HashMap::new(),
+            peers: vec![],
+            table: Table::new(),
+            mrt_dump: None,
+            recorder: None,
+            roas: None,
+            rpki_policy: RpkiPolicy::Accept,
+            allowas_in: 0,
+            stats: HashMap::new(),
+            config: RouterConfig::default(),
+            as_notation: AsNotation::Asplain,
+            learned_asns: HashMap::new(),
+            leak_policy: LeakPolicy::Off,
+            notify_errors: false,
+            hold_time: 0,
+            sessions: HashMap::new(),
+            announced: HashMap::new(),
+            advertise_mode: AdvertiseMode::All,
+            adj_rib_out: AdjRibOut::default(),
+        }
+    }
 
-        router.asn = asn;
+    pub fn set_mrt_dump(&mut self, path: String) {
+        self.mrt_dump = Some(path);
+    }
+
+    pub fn set_recorder(&mut self, path: &str) -> Result<(), String> {
+        self.recorder = Some(Recorder::open(path)?);
         Ok(())
     }
 
-    // Creates a new Router instance
+    pub fn load_roas(&mut self, path: &str, policy: RpkiPolicy) -> Result<(), String> {
+        self.roas = Some(RoaSet::load(path)?);
+        self.rpki_policy = policy;
+        self.table
+            .set_depreference_invalid(policy == RpkiPolicy::Depreference);
+        Ok(())
+    }
+
+    pub fn load_config(&mut self, path: &str) -> Result<(), String> {
+        self.config = RouterConfig::load(path)?;
+        Ok(())
+    }
+
+    pub fn set_allowas_in(&mut self, allowas_in: usize) {
+        self.allowas_in = allowas_in;
+    }
+
+    pub fn set_leak_policy(&mut self, leak_policy: LeakPolicy) {
+        self.leak_policy = leak_policy;
+    }
+
+    pub fn set_notify_errors(&mut self, notify_errors: bool) {
+        self.notify_errors = notify_errors;
+    }
+
+    pub fn set_advertise_mode(&mut self, advertise_mode: AdvertiseMode) {
+        self.advertise_mode = advertise_mode;
+    }
+
+    pub fn set_hold_time(&mut self, hold_time: u64) {
+        self.hold_time = hold_time;
+    }
+
+    pub fn set_as_notation(&mut self, as_notation: AsNotation) {
+        self.as_notation = as_notation;
+    }
+
+    // Adds a neighbor to the router
     pub fn add_neighbor(
+        &mut self,
         neighbor_addr: &str,
         neighbor_port: &str,
         neighbor_relation: &str,
     ) -> Result<(), String> {
-        // Lock the mutex here
-        let mut router = GLOBAL_ROUTER
-            .lock()
-            .map_err(|e| format!("Failed to lock router: {}", e))?;
-
         // Get the relationship
         let relation = match neighbor_relation {
             "cust" => NeighborType::Cust,
@@ -95,15 +207,15 @@ impl Router {
             .set_nonblocking(true)
             .map_err(|e| format!("{e} -> failed to switch to non-blockinf mode"))?;
 
-        router.sockets.insert(neighbor_addr.to_string(), udp_socket);
-        router
-            .ports
+        self.sockets.insert(neighbor_addr.to_string(), udp_socket);
+        self.ports
             .insert(neighbor_addr.to_string(), neighbor_port.to_string());
-        router.relations.insert(neighbor_addr.to_string(), relation);
-        let mut peers = GLOBAL_PEER
-            .lock()
-            .map_err(|e| format!("{e} -> failed to lock peer vector"))?;
-        peers.push(neighbor_addr.to_string());
+        self.relations.insert(neighbor_addr.to_string(), relation);
+        self.stats
+            .insert(neighbor_addr.to_string(), NeighborStats::default());
+        self.sessions
+            .insert(neighbor_addr.to_string(), Session::new(self.hold_time));
+        self.peers.push(neighbor_addr.to_string());
 
         Ok(())
     }
@@ -111,314 +223,834 @@ impl Router {
     /**
      * This function is used to start Router
      * It sends a handshake to each registed neighbor at first,
-     * and then keep listening each scoket for any incoming messages
+     * and then sleeps until some sockets are readable or a timer expires,
+     * handling the incoming messages of the ready sockets in turn
      */
-    pub fn start_router() -> Result<(), String> {
-        let router = GLOBAL_ROUTER
-            .lock()
-            .map_err(|e| format!("Failed to lock router: {}", e))?;
-
-        let peers = GLOBAL_PEER
-            .lock()
-            .map_err(|e| format!("{e} -> failed to lock peer vector"))?;
-
+    pub fn start_router(&mut self) -> Result<(), RouterError> {
         // Iterate through all the registered neighbors and do the handshake
-        for ip_addr in peers.iter() {
-            let (socket, port) = (
-                router.sockets.get(ip_addr).unwrap(),
-                router.ports.get(ip_addr).unwrap(),
-            );
-            // Our ip address for this specific port
-            let local_ip = format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1");
-            // Create the handshake message
-            let handshake_msg = json!({"src":local_ip,"dst":ip_addr,"type": "handshake","msg":{}});
+        for ip_addr in self.peers.clone() {
+            self.open_session(&ip_addr)?;
+        }
 
-            socket.send_to(handshake_msg.to_string().as_bytes(), format!("127.0.0.1:{port}")).map_err(|e| format!("{e} -> failed to send handshake message to {ip_addr} with 127.0.0.1:{port}"))?;
+        // Register every neighbor socket, identified by its index in the peer vector
+        let mut poll =
+            Poll::new().map_err(RouterError::io("failed to create the poll".to_string()))?;
+        for (ind, ip_addr) in self.peers.iter().enumerate() {
+            let fd = self.sockets[ip_addr].as_raw_fd();
+            poll.registry()
+                .register(&mut SourceFd(&fd), Token(ind), Interest::READABLE)
+                .map_err(RouterError::io(format!(
+                    "failed to register the socket of {ip_addr}"
+                )))?;
         }
 
         // Create read buffer
         let mut buf: [u8; 2048] = [0; 2048];
+        let mut events = Events::with_capacity(self.peers.len().max(1));
         loop {
-            // Iterate through all the neighbors
-            for ip_addr in peers.iter() {
-                let socket = router.sockets.get(ip_addr).unwrap();
-                // Listen to any incoming message
+            // Sleep until a socket is readable or the next timer expires
+            let timeout = self
+                .next_deadline()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(e) = poll.poll(&mut events, timeout) {
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(RouterError::io("failed to poll the sockets".to_string())(e));
+            }
+
+            // Serve the ready sockets in turn, one message at a time, until all of them are drained
+            let mut ready: VecDeque<usize> = events.iter().map(|event| event.token().0).collect();
+            while let Some(ind) = ready.pop_front() {
+                let ip_addr = self.peers[ind].clone();
+                let socket = self.sockets.get(&ip_addr).unwrap();
                 match socket.recv(&mut buf) {
-                    Ok(_) => {
-                        let msg = Router::read_to_string(&mut buf)?;
-                        buf.fill(0);
-                        let mut json_obj: Message = serde_json::from_str(&msg)
-                            .map_err(|e| format!("{e} -> failed to parse JSON object"))?;
-                        match json_obj.r#type.as_str() {
-                            "update" => {
-                                router.handle_update_message(&mut json_obj, ip_addr)?;
-                            }
-                            "withdraw" => {
-                                router.handle_withdraw_message(&json_obj, ip_addr)?;
-                            }
-                            "dump" => {
-                                router.handle_dump_message(&json_obj, ip_addr)?;
-                            }
-                            "data" => {
-                                router.handle_data_message(&json_obj, ip_addr)?;
-                            }
-                            _ => {}
+                    Ok(len) => {
+                        // A bad message only costs itself, the router keeps serving the others
+                        let result = Router::read_to_string(&buf, len)
+                            .and_then(|msg| self.handle_message(&msg, &ip_addr));
+                        if let Err(e) = result {
+                            self.handle_error(e, &ip_addr);
                         }
+                        ready.push_back(ind);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => println!("{e} -> failed to receive from {ip_addr}"),
+                }
+            }
+
+            self.handle_timers(Instant::now());
+        }
+    }
+
+    /// Handles a message received from a neighbor according to its type.
+    /// # Arguments
+    /// * `msg` - The received message.
+    /// * `ip_addr` - The IP address of the neighbor that sent the message.
+    fn handle_message(&mut self, msg: &str, ip_addr: &str) -> Result<(), RouterError> {
+        let message: Message = serde_json::from_str(msg).map_err(RouterError::Parse)?;
+        // Any valid message shows that the neighbor is alive
+        let session = self.sessions.get_mut(ip_addr).unwrap();
+        let mut answer = false;
+        let established = match &message.body {
+            Body::Handshake(handshake) => {
+                answer = session.open(handshake.holdTime, Instant::now());
+                false
+            }
+            // A neighbor closing the session does not open it
+            Body::Notification(_) => {
+                session.closed();
+                false
+            }
+            _ => session.received(Instant::now()),
+        };
+        // A neighbor reopening a session we took down, or it closed, waits for our handshake
+        if answer {
+            self.send_handshake(ip_addr)?;
+        }
+        // A session opened implicitly only gets the routes missing from the Adj-RIB-Out
+        if established {
+            if let Err(e) = self.sync_neighbor(ip_addr, false) {
+                println!("{e} -> failed to send the routes to {ip_addr}");
+            }
+        }
+        // Routes are only accepted over an established session
+        if matches!(message.body, Body::Update(_) | Body::Withdraw(_))
+            && self.sessions[ip_addr].state() != SessionState::Established
+        {
+            println!(
+                "Ignored {} from {ip_addr}: the session is not established",
+                message.type_name()
+            );
+            return Ok(());
+        }
+        let result = match &message.body {
+            Body::Update(update) => self.handle_update_message(update, ip_addr),
+            Body::Withdraw(prefixes) => self.handle_withdraw_message(prefixes, ip_addr),
+            Body::Dump(_) => self.handle_dump_message(&message, ip_addr),
+            Body::Data(_) => self.handle_data_message(&message, ip_addr),
+            Body::Show(Control::Request(_)) => self.handle_show_message(&message, ip_addr),
+            Body::Neighbors(Control::Request(_)) => {
+                self.handle_neighbors_message(&message, ip_addr)
+            }
+            Body::Handshake(handshake) => self.handle_handshake_message(handshake, ip_addr),
+            Body::Keepalive(_) => Ok(()),
+            Body::Notification(notification) => {
+                self.handle_notification_message(notification, ip_addr)
+            }
+            Body::RouteRefresh(_) => self.handle_route_refresh_message(ip_addr),
+            Body::Clear(clear) => self.handle_clear_message(clear),
+            Body::Refresh(refresh) => self.handle_refresh_message(refresh),
+            // Only neighbors receive these messages
+            Body::Table(_)
+            | Body::NoRoute(_)
+            | Body::Show(Control::Reply(_))
+            | Body::Neighbors(Control::Reply(_))
+            | Body::Error(_) => Err(RouterError::invalid(
+                message.type_name(),
+                "unexpected message for a router",
+            )),
+        };
+        // Record the message together with the route it left in the table,
+        // unless it was malformed and never reached the table
+        if !matches!(&result, Err(e) if e.is_malformed()) {
+            self.record(&message, ip_addr);
+        }
+        result
+    }
+
+    /// Records a message received from a neighbor, if recording is enabled.
+    /// Updates are recorded with the route installed after the import policies,
+    /// so that replaying the recording gives the final state of the table.
+    /// # Arguments
+    /// * `message` - The received message.
+    /// * `ip_addr` - The IP address of the neighbor that sent the message.
+    fn record(&self, message: &Message, ip_addr: &str) {
+        let recorder = match &self.recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        let route = match &message.body {
+            Body::Update(update) => {
+                let prefix = (update.network.clone(), update.netmask.clone());
+                let accepted = self
+                    .announced
+                    .get(ip_addr)
+                    .is_some_and(|announced| announced.contains(&prefix));
+                if accepted {
+                    self.table.route(&update.network, &update.netmask, ip_addr)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if let Err(e) = recorder.record(ip_addr, message, route.as_ref()) {
+            println!("{e}");
+        }
+    }
+
+    /// Returns when the next timer of the router expires, if any.
+    /// The event loop wakes up at that time at the latest, even if no message arrives.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .flat_map(|session| [session.hold_deadline(), session.keepalive_deadline()])
+            .flatten()
+            .min()
+    }
+
+    /// Runs the timers of the router that expired by the given time.
+    /// It is called every time the event loop wakes up.
+    fn handle_timers(&mut self, now: Instant) {
+        for ip_addr in self.peers.clone() {
+            // A failing neighbor does not hold back the timers of the others
+            if let Err(e) = self.handle_neighbor_timers(&ip_addr, now) {
+                println!("{e} -> failed to run the timers of {ip_addr}");
+            }
+        }
+    }
+
+    /// Runs the timers of the session with a neighbor that expired by the given time.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    /// * `now` - The current time.
+    fn handle_neighbor_timers(&mut self, ip_addr: &str, now: Instant) -> Result<(), RouterError> {
+        let session = &self.sessions[ip_addr];
+        if session
+            .hold_deadline()
+            .is_some_and(|deadline| deadline <= now)
+        {
+            println!("Hold timer expired for {ip_addr}");
+            self.reset_session(ip_addr, "Hold timer expired")?;
+        } else if session
+            .keepalive_deadline()
+            .is_some_and(|deadline| deadline <= now)
+        {
+            let keepalive_msg = Message::new(
+                &format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
+                ip_addr,
+                Body::Keepalive(Empty {}),
+            );
+            Router::send(&self.sockets[ip_addr], &self.ports[ip_addr], &keepalive_msg)?;
+            self.sessions.get_mut(ip_addr).unwrap().keepalive_sent(now);
+        }
+        Ok(())
+    }
+
+    /// Opens the session with a neighbor by sending our handshake.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    fn open_session(&mut self, ip_addr: &str) -> Result<(), RouterError> {
+        self.sessions.get_mut(ip_addr).unwrap().connect();
+        self.send_handshake(ip_addr)?;
+        self.sessions
+            .get_mut(ip_addr)
+            .unwrap()
+            .open_sent(Instant::now());
+        Ok(())
+    }
+
+    /// Sends our handshake to a neighbor, which announces our AS and hold time.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    fn send_handshake(&self, ip_addr: &str) -> Result<(), RouterError> {
+        let session = &self.sessions[ip_addr];
+        let (socket, port) = (&self.sockets[ip_addr], &self.ports[ip_addr]);
+        // Our ip address for this specific port
+        let local_ip = format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1");
+        // Create the handshake message, announcing our AS so that the neighbor can learn it
+        let handshake_msg = Message::new(
+            &local_ip,
+            ip_addr,
+            Body::Handshake(Handshake {
+                asn: Some(self.asn),
+                holdTime: Some(session.local_hold_time()),
+            }),
+        );
+
+        Router::send(socket, port, &handshake_msg)
+    }
+
+    /// Resets the session with a neighbor: the neighbor is told why with a "notification"
+    /// message, its routes are flushed, and the session is opened again.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    /// * `reason` - Why the session is reset.
+    fn reset_session(&mut self, ip_addr: &str, reason: &str) -> Result<(), RouterError> {
+        let notification_msg = Message::new(
+            &format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
+            ip_addr,
+            Body::Notification(ErrorReport {
+                error: reason.to_string(),
+            }),
+        );
+        Router::send(
+            &self.sockets[ip_addr],
+            &self.ports[ip_addr],
+            &notification_msg,
+        )?;
+        self.session_down(ip_addr)?;
+        self.open_session(ip_addr)
+    }
+
+    /// Takes the session with a neighbor down and flushes its routes.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    fn session_down(&mut self, ip_addr: &str) -> Result<(), RouterError> {
+        self.sessions.get_mut(ip_addr).unwrap().down();
+        // The neighbor forgets whatever we announced to it
+        self.adj_rib_out.clear(ip_addr);
+        self.flush_neighbor(ip_addr)
+    }
+
+    /// Removes every route learned from a neighbor whose session went down.
+    /// For every prefix the neighbor announced, the neighbors get the best remaining route
+    /// in place of the route announced to them, or a withdrawal if no other route may be sent to them.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    pub fn flush_neighbor(&mut self, ip_addr: &str) -> Result<(), RouterError> {
+        let flushed = self.table.flush(ip_addr);
+        println!("Flushed {flushed} routes of {ip_addr}");
+        let prefixes = self.announced.remove(ip_addr).unwrap_or_default();
+        // Record the flush as the withdrawal of every route of the neighbor
+        let withdraw_msg = Message::new(
+            ip_addr,
+            &format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
+            Body::Withdraw(
+                prefixes
+                    .iter()
+                    .map(|(network, netmask)| Prefix {
+                        network: network.clone(),
+                        netmask: netmask.clone(),
+                    })
+                    .collect(),
+            ),
+        );
+        self.record(&withdraw_msg, ip_addr);
+        for (network, netmask) in prefixes {
+            self.advertise(&Prefix { network, netmask })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the best route to a prefix among the neighbors that announced it.
+    /// # Arguments
+    /// * `prefix` - The prefix of the route.
+    fn best_path(&self, prefix: &Prefix) -> Option<Network> {
+        let key = (prefix.network.clone(), prefix.netmask.clone());
+        let mut best: Option<Network> = None;
+        for (peer, announced) in self.announced.iter() {
+            if !announced.contains(&key) {
+                continue;
+            }
+            if let Some(net) = self.table.route(&prefix.network, &prefix.netmask, peer) {
+                let preferred = match &best {
+                    Some(best) => self.table.is_preferred(&net, best),
+                    None => true,
+                };
+                if preferred {
+                    best = Some(net);
+                }
+            }
+        }
+        best
+    }
+
+    /// Applies the relationships and the export policies to the best route to a prefix.
+    /// # Arguments
+    /// * `best` - The best route to the prefix, if any.
+    /// * `nei_ip` - The IP address of the neighbor the route would be sent to.
+    /// # Returns
+    /// * `Option<Network>` - The route as it must be sent to the neighbor, or None if the neighbor must not get a route to the prefix.
+    fn export_best(&self, best: Option<&Network>, nei_ip: &str) -> Option<Network> {
+        best.filter(|net| net.peer != nei_ip && self.relation_permits(&net.peer, nei_ip))
+            .and_then(|net| self.export_route(nei_ip, net))
+    }
+
+    /// Sends a neighbor the best route to every prefix it may see, and a withdrawal for every
+    /// prefix of its Adj-RIB-Out that it may no longer see, followed by an end-of-RIB marker,
+    /// an empty "withdraw" message (RFC 4724).
+    /// It is used when the session comes up and when the neighbor asks for a route refresh.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor.
+    /// * `full` - Whether every route is sent again, followed by the end-of-RIB marker,
+    ///   or only the routes that differ from its Adj-RIB-Out, without the marker.
+    fn sync_neighbor(&mut self, ip_addr: &str, full: bool) -> Result<(), RouterError> {
+        let mut prefixes: BTreeSet<(String, String)> =
+            self.announced.values().flatten().cloned().collect();
+        prefixes.extend(self.adj_rib_out.prefixes(ip_addr));
+        for (network, netmask) in prefixes {
+            let prefix = Prefix { network, netmask };
+            match self.export_best(self.best_path(&prefix).as_ref(), ip_addr) {
+                Some(exported) => {
+                    let announced = self
+                        .adj_rib_out
+                        .get(ip_addr, &prefix.network, &prefix.netmask);
+                    if full || announced != Some(&self.announcement(ip_addr, &exported)) {
+                        self.send_update(ip_addr, &exported)?;
                     }
-                    Err(_) => {
-                        continue;
+                }
+                None => self.send_withdraw(ip_addr, &prefix)?,
+            }
+        }
+        if !full {
+            return Ok(());
+        }
+
+        let end_of_rib = Message::new(
+            &format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
+            ip_addr,
+            Body::Withdraw(vec![]),
+        );
+        Router::send(&self.sockets[ip_addr], &self.ports[ip_addr], &end_of_rib)
+    }
+
+    /// Recomputes the best route to a prefix and sends it to every neighbor whose announced route
+    /// it changes, or a withdrawal to the neighbors that may no longer get any route to the prefix.
+    /// Neighbors whose Adj-RIB-Out already holds the route hear nothing.
+    /// # Arguments
+    /// * `prefix` - The prefix of the route.
+    fn advertise(&mut self, prefix: &Prefix) -> Result<(), RouterError> {
+        let best = self.best_path(prefix);
+
+        for nei_ip in self.peers.clone() {
+            let exported = self.export_best(best.as_ref(), &nei_ip);
+            let announced = self
+                .adj_rib_out
+                .get(&nei_ip, &prefix.network, &prefix.netmask);
+            match exported {
+                Some(exported) => {
+                    if announced != Some(&self.announcement(&nei_ip, &exported)) {
+                        self.send_update(&nei_ip, &exported)?;
                     }
                 }
+                None => self.send_withdraw(&nei_ip, prefix)?,
             }
         }
+        Ok(())
+    }
+
+    /// Sends an "update" message announcing a route to a neighbor, and records it in its Adj-RIB-Out.
+    /// Nothing is sent to a neighbor whose session does not take routes.
+    /// # Arguments
+    /// * `nei_ip` - The IP address of the neighbor the route is sent to.
+    /// * `net` - The route as it is exported to the neighbor.
+    fn send_update(&mut self, nei_ip: &str, net: &Network) -> Result<(), RouterError> {
+        if !self.sessions[nei_ip].receives_routes() {
+            return Ok(());
+        }
+        let update = self.announcement(nei_ip, net);
+        let update_msg = Message::new(
+            &format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
+            nei_ip,
+            Body::Update(update.clone()),
+        );
+        Router::send(&self.sockets[nei_ip], &self.ports[nei_ip], &update_msg)?;
+        self.adj_rib_out.announce(nei_ip, update);
+        Ok(())
+    }
+
+    /// Removes a prefix from the Adj-RIB-Out of a neighbor, and sends it a "withdraw" message
+    /// for the prefix if a route to it had been announced to the neighbor.
+    /// Nothing is sent to a neighbor whose session does not take routes.
+    /// # Arguments
+    /// * `nei_ip` - The IP address of the neighbor the withdrawal is sent to.
+    /// * `prefix` - The withdrawn prefix.
+    fn send_withdraw(&mut self, nei_ip: &str, prefix: &Prefix) -> Result<(), RouterError> {
+        if !self.sessions[nei_ip].receives_routes()
+            || !self
+                .adj_rib_out
+                .withdraw(nei_ip, &prefix.network, &prefix.netmask)
+        {
+            return Ok(());
+        }
+        let withdraw_msg = Message::new(
+            &format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
+            nei_ip,
+            Body::Withdraw(vec![prefix.clone()]),
+        );
+        Router::send(&self.sockets[nei_ip], &self.ports[nei_ip], &withdraw_msg)
     }
 
-    /// Reads a buffer and returns a string.
-    fn read_to_string(buf: &mut [u8]) -> Result<String, String> {
-        for ind in 0..buf.len() {
-            if buf[ind] == 0 {
-                return Ok(String::from_utf8_lossy(&buf[..ind]).to_string());
+    /// Logs an error that occurred while handling a message from a neighbor.
+    /// Malformed messages are counted in the statistics of the neighbor and,
+    /// if enabled, reported back to it with an "error" message.
+    /// # Arguments
+    /// * `error` - The error.
+    /// * `ip_addr` - The IP address of the neighbor that sent the message.
+    fn handle_error(&mut self, error: RouterError, ip_addr: &str) {
+        println!("{error} -> from {ip_addr}");
+        if !error.is_malformed() {
+            return;
+        }
+        self.stats.entry(ip_addr.to_string()).or_default().malformed += 1;
+
+        if self.notify_errors {
+            let error_msg = Message::new(
+                &format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
+                ip_addr,
+                Body::Error(ErrorReport {
+                    error: error.to_string(),
+                }),
+            );
+            let (socket, port) = (&self.sockets[ip_addr], &self.ports[ip_addr]);
+            if let Err(e) = Router::send(socket, port, &error_msg) {
+                println!("{e} -> to {ip_addr}");
             }
         }
-        Err(format!("Data incomplete"))
+    }
+
+    /// Sends a message to a neighbor through the given socket.
+    /// # Arguments
+    /// * `socket` - The socket the message is sent from.
+    /// * `port` - The port of the neighbor.
+    /// * `message` - The message.
+    fn send(socket: &UdpSocket, port: &str, message: &Message) -> Result<(), RouterError> {
+        socket
+            .send_to(&message.to_bytes(), format!("127.0.0.1:{port}"))
+            .map_err(RouterError::send(message.type_name()))?;
+        Ok(())
+    }
+
+    /// Reads the first bytes of a buffer and returns a string.
+    /// Datagrams that fill the whole buffer may have been truncated.
+    fn read_to_string(buf: &[u8], len: usize) -> Result<String, RouterError> {
+        if len >= buf.len() {
+            return Err(RouterError::Incomplete);
+        }
+        Ok(String::from_utf8_lossy(&buf[..len]).to_string())
     }
 
     /// Processes and forwards "update" messages according to BGP policies.
     /// # Arguments
-    /// * `json_obj` - A mutable reference to the received "update" message.
+    /// * `update` - The body of the received "update" message.
     /// * `ip_addr` - The IP address of the neighbor that sent the "update" message.
     fn handle_update_message(
-        &self,
-        json_obj: &mut Message,
+        &mut self,
+        update: &Update,
         ip_addr: &str, // neighbor ip address
-    ) -> Result<(), String> {
-        let socket = self.sockets.get(ip_addr).unwrap();
-        // Create new ASPath array
-        let mut new_arr: Vec<Value> = vec![json!(self.asn.clone())];
-        if let Value::Array(arr) = json_obj.msg["ASPath"].clone() {
-            for val in arr.iter() {
-                new_arr.push(val.clone());
+    ) -> Result<(), RouterError> {
+        // Include peer in the route for table row update
+        let mut net = update
+            .clone()
+            .into_network(ip_addr)
+            .map_err(|e| RouterError::invalid("update", e))?;
+        let prefix = Prefix {
+            network: net.network.clone(),
+            netmask: net.netmask.clone(),
+        };
+        let key = (prefix.network.clone(), prefix.netmask.clone());
+        if !self.accept_route(&mut net, ip_addr) {
+            // The rejected route still replaces whatever this neighbor announced before,
+            // but the rows of a covering prefix it announced must stay whole
+            let was_announced = self
+                .announced
+                .get_mut(ip_addr)
+                .is_some_and(|announced| announced.remove(&key));
+            if !was_announced {
+                return Ok(());
             }
+            self.table.withdraw(&net.network, &net.netmask, ip_addr);
+            // The route of this neighbor may have been the one announced to the others
+            return match self.advertise_mode {
+                AdvertiseMode::All => self.forward_withdraw(&prefix, ip_addr),
+                AdvertiseMode::Best => self.advertise(&prefix),
+            };
         }
-        // Include peer in the message for table row update
-        json_obj.msg["peer"] = json!(ip_addr);
-        // Get global table for updating
-        let mut table = GLOBAL_TABLE
-            .lock()
-            .map_err(|e| format!("{e} -> failed to lock the table"))?;
+        self.announced
+            .entry(ip_addr.to_string())
+            .or_default()
+            .insert(key);
         // Update the table
-        let net: Network = serde_json::from_str(&json_obj.msg.to_string()).unwrap();
-        table.update(net);
+        self.table.update(net.clone());
+
+        // Only announce the changes of the best route
+        if self.advertise_mode == AdvertiseMode::Best {
+            return self.advertise(&prefix);
+        }
 
         // Logic for forwarding the announcement
         // Decide who to forward the announcement to
-        match self.relations[ip_addr] {
-            // If sender is my customer, I will forward to everyone
-            NeighborType::Cust => {
-                for (nei_ip, nei_port) in self.ports.iter() {
-                    // Send the "update" message to every neighbor except the origin
-                    if nei_ip != ip_addr {
-                        // Customize update message
-                        let update_msg = json!({
-                            "src": format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
-                            "dst": nei_ip,
-                            "type": "update",
-                            "msg": {
-                                "network": &json_obj.msg["network"],
-                                "netmask": &json_obj.msg["netmask"],
-                                "ASPath": json!(new_arr),
-                            }
-
-                        });
-                        socket.send_to(update_msg.to_string().as_bytes(),format!("127.0.0.1:{nei_port}")).map_err(|e| format!("{e} -> failed to send update message to {ip_addr} with 127.0.0.1:{nei_port}"))?;
-                    }
-                }
+        for nei_ip in self.peers.clone() {
+            // Send the "update" message to every neighbor except the origin
+            // that the relationships and the export policies allow
+            if nei_ip == ip_addr {
+                continue;
             }
-            // If sender is not my customer, I will only forward your announcement to my customer
-            _ => {
-                for (nei_ip, nei_port) in self.ports.iter() {
-                    // Send the "update" message to every neighbor except the origin
-                    if nei_ip != ip_addr {
-                        // Forward announcement only to my customer
-                        match self.relations[nei_ip] {
-                            NeighborType::Cust => {
-                                // Customize update message
-                                let update_msg = json!({
-                                    "src": format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
-                                    "dst": nei_ip,
-                                    "type": "update",
-                                    "msg": {
-                                        "network": &json_obj.msg["network"],
-                                        "netmask": &json_obj.msg["netmask"],
-                                        "ASPath": json!(new_arr),
-                                    }
-
-                                });
-                                socket.send_to(update_msg.to_string().as_bytes(), format!("127.0.0.1:{nei_port}")).map_err(|e| format!("{e} -> failed to send update message to {ip_addr} with 127.0.0.1:{nei_port}"))?;
-                            }
-                            // Do nothing, if the neighbor is not my customer
-                            _ => {}
-                        }
-                    }
-                }
+            let exported = if self.relation_permits(ip_addr, &nei_ip) {
+                self.export_route(&nei_ip, &net)
+            } else {
+                None
+            };
+            match exported {
+                Some(exported) => self.send_update(&nei_ip, &exported)?,
+                // The route announced to the neighbor before may no longer be sent to it
+                None => self.send_withdraw(&nei_ip, &prefix)?,
             }
         }
         Ok(())
     }
 
+    /// Decides if the relationships allow forwarding announcements from one neighbor to another.
+    /// If the sender is my customer, I will forward to everyone.
+    /// If the sender is not my customer, I will only forward its announcements to my customers.
+    /// # Arguments
+    /// * `from` - The IP address of the neighbor that sent the announcement.
+    /// * `to` - The IP address of the neighbor the announcement would be forwarded to.
+    fn relation_permits(&self, from: &str, to: &str) -> bool {
+        matches!(self.relations[from], NeighborType::Cust)
+            || matches!(self.relations[to], NeighborType::Cust)
+    }
+
+    /// Applies the export policies to a route that would be sent to a neighbor.
+    /// # Arguments
+    /// * `nei_ip` - The IP address of the neighbor the route would be sent to.
+    /// * `net` - The route as it is stored in the table.
+    /// # Returns
+    /// * `Option<Network>` - The route as it must be sent to the neighbor, or None if it must not be sent.
+    fn export_route(&self, nei_ip: &str, net: &Network) -> Option<Network> {
+        // Neighbors are external peers unless they are known to be in our AS
+        let external = self.neighbor_asn(nei_ip) != Some(self.asn);
+        if !Community::permits_export(&net.communities, external) {
+            return None;
+        }
+
+        // Never send routes limited to customers up or sideways, and limit the others if needed
+        let mut otc = net.otc;
+        if self.leak_policy != LeakPolicy::Off
+            && !self.role(nei_ip).permits_egress(&mut otc, self.asn)
+        {
+            return None;
+        }
+
+        let neighbor = self.config.neighbor(nei_ip);
+        if !neighbor.export_as_path.permits(&net.ASPath) || !neighbor.export_community.permits(net)
+        {
+            return None;
+        }
+
+        // Apply the export route-map of the neighbor on a copy of the stored route,
+        // after stripping the private AS numbers so that its prepends are kept
+        let mut exported = Network { otc, ..net.clone() };
+        neighbor.remove_private_as.apply(&mut exported.ASPath);
+        if !self.config.apply_route_map(
+            &neighbor.export_route_map,
+            &mut exported,
+            &self.relations[&net.peer],
+            self.asn,
+        ) {
+            return None;
+        }
+        Some(exported)
+    }
+
+    /// Builds the announcement of a route to a neighbor.
+    /// Our AS number is prepended to the path, once more for every prepend configured
+    /// for the neighbor, and the (large) communities and the OTC attribute are propagated.
+    /// # Arguments
+    /// * `nei_ip` - The IP address of the neighbor the route is sent to.
+    /// * `net` - The route as it is exported to the neighbor.
+    fn announcement(&self, nei_ip: &str, net: &Network) -> Update {
+        // Create new ASPath array
+        let prepend = self.config.neighbor(nei_ip).prepend;
+        let mut as_path = vec![self.asn; 1 + prepend];
+        as_path.extend(net.ASPath.iter());
+
+        Update::announce(net, as_path)
+    }
+
+    /// Applies the import policies to a route received from a neighbor.
+    /// Rejections are counted in the statistics of the neighbor.
+    /// # Arguments
+    /// * `net` - The received route, whose localpref and RPKI state get assigned.
+    /// * `ip_addr` - The IP address of the neighbor that sent the route.
+    /// # Returns
+    /// * `bool` - true if the route may be installed, false if it must be rejected.
+    fn accept_route(&mut self, net: &mut Network, ip_addr: &str) -> bool {
+        let neighbor_asn = self.neighbor_asn(ip_addr);
+        let role = self.role(ip_addr);
+        let stats = self.stats.entry(ip_addr.to_string()).or_default();
+
+        // Reject routes that already went through us, unless allowas-in permits it
+        let own_asn = net.ASPath.iter().filter(|asn| **asn == self.asn).count();
+        if own_asn > self.allowas_in {
+            stats.loops_rejected += 1;
+            return false;
+        }
+
+        // Enforce first AS: an external neighbor must have put its own AS in front of the path
+        if let Some(neighbor_asn) = neighbor_asn {
+            if neighbor_asn != self.asn && net.ASPath.first() != Some(&neighbor_asn) {
+                stats.first_as_rejected += 1;
+                return false;
+            }
+        }
+
+        // Detect route leaks from the OTC attribute, which gets set on routes that must stay downstream
+        net.leak = false;
+        if self.leak_policy != LeakPolicy::Off && role.check_ingress(&mut net.otc, neighbor_asn) {
+            stats.leaks_detected += 1;
+            println!(
+                "Route leak detected: {}/{} from {ip_addr} carries OTC {:?}",
+                net.network, net.netmask, net.otc
+            );
+            if self.leak_policy == LeakPolicy::Drop {
+                return false;
+            }
+            net.leak = true;
+        }
+
+        // Normalize the communities so that routes with the same communities aggregate
+        net.communities.sort();
+        net.communities.dedup();
+        net.largeCommunities.sort();
+        net.largeCommunities.dedup();
+
+        // Reject routes denied by the import filters of the neighbor
+        let neighbor = self.config.neighbor(ip_addr);
+        if !neighbor.import_as_path.permits(&net.ASPath) || !neighbor.import_community.permits(net)
+        {
+            stats.import_filtered += 1;
+            return false;
+        }
+
+        // Enforce the preferences of the relationship with the neighbor
+        net.localpref = self
+            .config
+            .localpref
+            .assign(&self.relations[ip_addr], net.localpref);
+
+        // Apply the import route-map of the neighbor
+        let neighbor = self.config.neighbor(ip_addr);
+        if !self.config.apply_route_map(
+            &neighbor.import_route_map,
+            net,
+            &self.relations[ip_addr],
+            self.asn,
+        ) {
+            stats.import_filtered += 1;
+            return false;
+        }
+
+        // Validate the origin of the route against the ROA set, never trusting the sender's state
+        net.rpki = self.roas.as_ref().map(|roas| roas.validate(net));
+        if net.rpki == Some(RpkiState::Invalid) && self.rpki_policy == RpkiPolicy::Drop {
+            stats.rpki_dropped += 1;
+            return false;
+        }
+
+        true
+    }
+
+    /// Returns the AS number of a neighbor, as configured or learned from its handshake.
+    fn neighbor_asn(&self, ip_addr: &str) -> Option<u32> {
+        self.config
+            .neighbor(ip_addr)
+            .asn
+            .or(self.learned_asns.get(ip_addr).copied())
+    }
+
+    /// Returns our BGP role on the session with a neighbor, as configured or from the relationship.
+    fn role(&self, ip_addr: &str) -> Role {
+        self.config
+            .neighbor(ip_addr)
+            .role
+            .unwrap_or(Role::from_relation(&self.relations[ip_addr]))
+    }
+
     /// Processes and forwards "withdraw" messages according to BGP policies.
+    /// In best mode, the best route to every withdrawn prefix is recomputed, so that the neighbors
+    /// get an alternate route in place of the withdrawn one, and a withdrawal only if none is left.
     /// # Arguments
-    /// * `json_obj` - A reference to the received "withdraw" message.
+    /// * `prefixes` - The prefixes of the received "withdraw" message.
     /// * `ip_addr` - The IP address of the neighbor that sent the "withdraw" message.
-    fn handle_withdraw_message(&self, json_obj: &Message, ip_addr: &str) -> Result<(), String> {
-        for _network in json_obj.msg.as_array().unwrap() {
-            let network = _network["network"].as_str().unwrap();
-            let netmask = _network["netmask"].as_str().unwrap();
-
-            // Get the socket for the neighbor
-            let socket = self.sockets.get(ip_addr).unwrap();
-            // Get global table for updating
-            let mut table = GLOBAL_TABLE
-                .lock()
-                .map_err(|e| format!("{e} -> failed to lock the table"))?;
-
+    fn handle_withdraw_message(
+        &mut self,
+        prefixes: &[Prefix],
+        ip_addr: &str,
+    ) -> Result<(), RouterError> {
+        // Reject the whole message before any of its prefixes reaches the table
+        for prefix in prefixes {
+            prefix
+                .validate()
+                .map_err(|e| RouterError::invalid("withdraw", e))?;
+        }
+        for prefix in prefixes {
             // Update the table
-            table.withdraw(network, netmask, ip_addr);
-
-            // Logic for forwarding the announcement
-            // Decide who to forward the announcement to
-            match self.relations[ip_addr] {
-                // If sender is my customer, I will forward to everyone
-                NeighborType::Cust => {
-                    for (nei_ip, nei_port) in self.ports.iter() {
-                        // Send the "withdraw" message to every neighbor except the origin
-                        if nei_ip != ip_addr {
-                            // Customize withdraw message
-                            let withdraw_msg = json!({
-                                "src": format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
-                                "dst": nei_ip,
-                                "type": "withdraw",
-                                "msg": [{
-                                    "network": _network["network"],
-                                    "netmask": _network["netmask"],
-                                }]
-
-                            });
-                            socket.send_to(withdraw_msg.to_string().as_bytes(),format!("127.0.0.1:{nei_port}")).map_err(|e| format!("{e} -> failed to send update message to {ip_addr} with 127.0.0.1:{nei_port}"))?;
-                        }
-                    }
-                }
-                // If sender is not my customer, I will only forward your announcement to my customer
-                _ => {
-                    for (nei_ip, nei_port) in self.ports.iter() {
-                        // Send the "withdraw" message to every neighbor except the origin
-                        if nei_ip != ip_addr {
-                            // Forward announcement only to my customer
-                            match self.relations[nei_ip] {
-                                NeighborType::Cust => {
-                                    // Customize withdraw message
-                                    let withdraw_msg = json!({
-                                        "src": format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
-                                        "dst": nei_ip,
-                                        "type": "withdraw",
-                                        "msg": [{
-                                            "network": _network["network"],
-                                            "netmask": _network["netmask"],
-                                        }]
-
-                                    });
-                                    socket.send_to(withdraw_msg.to_string().as_bytes(), format!("127.0.0.1:{nei_port}")).map_err(|e| format!("{e} -> failed to send update message to {ip_addr} with 127.0.0.1:{nei_port}"))?;
-                                }
-                                // Do nothing, if the neighbor is not my customer
-                                _ => {}
-                            }
-                        }
-                    }
-                }
+            self.table
+                .withdraw(&prefix.network, &prefix.netmask, ip_addr);
+            if let Some(announced) = self.announced.get_mut(ip_addr) {
+                announced.remove(&(prefix.network.clone(), prefix.netmask.clone()));
+            }
+
+            match self.advertise_mode {
+                AdvertiseMode::All => self.forward_withdraw(prefix, ip_addr)?,
+                AdvertiseMode::Best => self.advertise(prefix)?,
             }
         }
 
         Ok(())
     }
 
+    /// Forwards the withdrawal of a prefix to the neighbors according to BGP policies.
+    /// # Arguments
+    /// * `prefix` - The withdrawn prefix.
+    /// * `ip_addr` - The IP address of the neighbor that withdrew the prefix.
+    fn forward_withdraw(&mut self, prefix: &Prefix, ip_addr: &str) -> Result<(), RouterError> {
+        // Logic for forwarding the withdrawal
+        // Decide who to forward the withdrawal to
+        for nei_ip in self.peers.clone() {
+            // Send the "withdraw" message to every neighbor except the origin
+            // that the relationships allow, if its Adj-RIB-Out holds the prefix
+            if nei_ip == ip_addr || !self.relation_permits(ip_addr, &nei_ip) {
+                continue;
+            }
+            self.send_withdraw(&nei_ip, prefix)?;
+        }
+
+        Ok(())
+    }
+
     /// Processes and forwards "data" messages according to BGP policies.
     /// # Arguments
-    /// * `json_obj` - A reference to the received "data" message.
+    /// * `message` - A reference to the received "data" message.
     /// * `ip_addr` - The IP address of the neighbor that sent the "data" message.
-    fn handle_data_message(&self, json_obj: &Message, ip_addr: &str) -> Result<(), String> {
-        let src_port = self.ports[ip_addr].clone();
+    fn handle_data_message(&self, message: &Message, ip_addr: &str) -> Result<(), RouterError> {
+        ipv4::parse(&message.dst).map_err(|e| RouterError::invalid("data", e))?;
+        let src_port = &self.ports[ip_addr];
         let socket = self.sockets.get(ip_addr).unwrap();
+        let no_route_message = Message::new(
+            &format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
+            &message.src,
+            Body::NoRoute(Empty {}),
+        );
         // We check if we can find the best route in the table
-        match Table::best_route(&json_obj.dst) {
+        match self.table.best_route(&message.dst) {
             Ok(peer_ip) => {
-                let data_message = json!({
-                    "src": json_obj.src,
-                    "dst": json_obj.dst,
-                    "type": "data",
-                    "msg": json_obj.msg
-                });
-
                 // Port that we will send the message to
                 let peer_port = self.ports.get(&peer_ip).unwrap();
 
                 match self.relations[ip_addr] {
                     // If source is my customer, I will forward to everyone
                     NeighborType::Cust => {
-                        socket
-                            .send_to(
-                                data_message.to_string().as_bytes(),
-                                format!("127.0.0.1:{}", peer_port),
-                            )
-                            .map_err(|e| format!("{e} -> failed to send the data message"))?;
+                        Router::send(socket, peer_port, message)?;
                     }
                     _ => {
                         // If source is not my customer, I will only forward your announcement to my customer
                         match self.relations.get(&peer_ip).unwrap() {
                             NeighborType::Cust => {
-                                socket
-                                    .send_to(
-                                        data_message.to_string().as_bytes(),
-                                        format!("127.0.0.1:{}", peer_port),
-                                    )
-                                    .map_err(|e| {
-                                        format!("{e} -> failed to send the data message")
-                                    })?;
+                                Router::send(socket, peer_port, message)?;
                             }
                             _ => {
-                                let no_route_message = json!({
-                                    "src": format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
-                                    "dst": json_obj.src,
-                                    "type": "no route",
-                                    "msg": {}
-                                });
-
-                                socket
-                                    .send_to(
-                                        no_route_message.to_string().as_bytes(),
-                                        format!("127.0.0.1:{}", src_port),
-                                    )
-                                    .map_err(|e| {
-                                        format!("{e} -> failed to send the no route message")
-                                    })?;
+                                Router::send(socket, src_port, &no_route_message)?;
                             }
                         }
                     }
                 }
             }
             Err(_) => {
-                let no_route_message = json!({
-                    "src": format!("{}{}", &ip_addr[..ip_addr.len() - 1], "1"),
-                    "dst": json_obj.src,
-                    "type": "no route",
-                    "msg": {}
-                });
-
-                socket
-                    .send_to(
-                        no_route_message.to_string().as_bytes(),
-                        format!("127.0.0.1:{}", src_port),
-                    )
-                    .map_err(|e| format!("{e} -> failed to send the no route message"))?;
+                Router::send(socket, src_port, &no_route_message)?;
             }
         }
         Ok(())
@@ -430,29 +1062,166 @@ impl Router {
     /// * `message` - A reference to the received "dump" message.
     /// * `ip_addr` - neighbor's ip address
     /// # Returns
-    /// * `Result<(), String>` - Ok(()) if the response was successfully sent, or Err(String) with an error message if not.
-    fn handle_dump_message(&self, message: &Message, ip_addr: &str) -> Result<(), String> {
+    /// * `Result<(), RouterError>` - Ok(()) if the response was successfully sent, or the error if not.
+    fn handle_dump_message(&self, message: &Message, ip_addr: &str) -> Result<(), RouterError> {
         let socket = self.sockets.get(ip_addr).unwrap();
-        let src_port = self.ports[ip_addr].clone();
-        let table = GLOBAL_TABLE
-            .lock()
-            .map_err(|e| format!("{e} -> failed to lock the table"))?;
+        let src_port = &self.ports[ip_addr];
         // Generate response to send back to the sender
-        let response = json!({
-            "src": message.dst,
-            "dst": message.src,
-            "type": "table",
-            "msg": json!(table.get_table().clone()) // Copy rounting table from global router
-        });
+        let response = Message::new(
+            &message.dst,
+            &message.src,
+            Body::Table(self.table.get_table().clone()), // Copy rounting table from the router
+        );
 
         // Find the correct port to send it back
-        socket
-            .send_to(
-                response.to_string().as_bytes(),
-                format!("127.0.0.1:{src_port}"),
-            )
-            .map_err(|e| format!("Failed to send table message: {}", e))?;
+        Router::send(socket, src_port, &response)?;
+
+        // Keep an MRT snapshot of the table for offline analysis
+        if let Some(path) = &self.mrt_dump {
+            if let Err(e) = std::fs::write(path, export_table(self.table.get_table())) {
+                println!("{e} -> failed to write MRT dump to {path}");
+            }
+        }
 
         Ok(())
     }
+
+    /// Handles a "show" control message received from a neighbor and responds with a "show" message.
+    /// The response carries the routing table rendered as "show ip bgp" text.
+    /// # Arguments
+    /// * `message` - A reference to the received "show" message.
+    /// * `ip_addr` - neighbor's ip address
+    /// # Returns
+    /// * `Result<(), RouterError>` - Ok(()) if the response was successfully sent, or the error if not.
+    fn handle_show_message(&self, message: &Message, ip_addr: &str) -> Result<(), RouterError> {
+        let socket = self.sockets.get(ip_addr).unwrap();
+        let src_port = &self.ports[ip_addr];
+        // Generate response to send back to the sender
+        let response = Message::new(
+            &message.dst,
+            &message.src,
+            Body::Show(Control::Reply(render_table(&self.table, self.as_notation))),
+        );
+
+        Router::send(socket, src_port, &response)
+    }
+
+    /// Handles a "neighbors" control message received from a neighbor and responds with a "neighbors" message.
+    /// The response maps every neighbor IP address to its relationship and statistics.
+    /// # Arguments
+    /// * `message` - A reference to the received "neighbors" message.
+    /// * `ip_addr` - neighbor's ip address
+    /// # Returns
+    /// * `Result<(), RouterError>` - Ok(()) if the response was successfully sent, or the error if not.
+    fn handle_neighbors_message(
+        &self,
+        message: &Message,
+        ip_addr: &str,
+    ) -> Result<(), RouterError> {
+        let socket = self.sockets.get(ip_addr).unwrap();
+        let src_port = &self.ports[ip_addr];
+        let mut neighbors = serde_json::Map::new();
+        for (nei_ip, relation) in self.relations.iter() {
+            let mut neighbor = json!(self.stats.get(nei_ip));
+            neighbor["relation"] = json!(relation);
+            neighbor["state"] = json!(self.sessions[nei_ip].state());
+            neighbor["advertised"] = json!(self.adj_rib_out.count(nei_ip));
+            if let Some(asn) = self.neighbor_asn(nei_ip) {
+                neighbor["asn"] = json!(asn);
+            }
+            neighbors.insert(nei_ip.clone(), neighbor);
+        }
+        // Generate response to send back to the sender
+        let response = Message::new(
+            &message.dst,
+            &message.src,
+            Body::Neighbors(Control::Reply(neighbors)),
+        );
+
+        Router::send(socket, src_port, &response)
+    }
+
+    /// Learns the AS number of a neighbor from the "asn" of its "handshake" message,
+    /// then sends it our routes now that its session is established.
+    /// A configured AS number always wins over the learned one.
+    /// # Arguments
+    /// * `handshake` - The body of the received "handshake" message.
+    /// * `ip_addr` - The IP address of the neighbor that sent the message.
+    fn handle_handshake_message(
+        &mut self,
+        handshake: &Handshake,
+        ip_addr: &str,
+    ) -> Result<(), RouterError> {
+        if let Some(asn) = handshake.asn {
+            if let Some(configured) = self.config.neighbor(ip_addr).asn {
+                if configured != asn {
+                    println!(
+                        "Neighbor {ip_addr} announced AS {asn} but is configured with AS {configured}"
+                    );
+                }
+            }
+            self.learned_asns.insert(ip_addr.to_string(), asn);
+        }
+        // The neighbor forgot whatever we announced before
+        self.adj_rib_out.clear(ip_addr);
+        self.sync_neighbor(ip_addr, true)
+    }
+
+    /// Takes the session with a neighbor down after it closed it with a "notification" message.
+    /// The session stays down until the neighbor sends a new handshake.
+    /// # Arguments
+    /// * `notification` - The body of the received "notification" message.
+    /// * `ip_addr` - The IP address of the neighbor that sent the message.
+    fn handle_notification_message(
+        &mut self,
+        notification: &ErrorReport,
+        ip_addr: &str,
+    ) -> Result<(), RouterError> {
+        println!(
+            "Neighbor {ip_addr} closed the session: {}",
+            notification.error
+        );
+        self.session_down(ip_addr)
+    }
+
+    /// Handles a "clear" control message by resetting the session with the given neighbor.
+    /// # Arguments
+    /// * `clear` - The body of the received "clear" message.
+    fn handle_clear_message(&mut self, clear: &NeighborCommand) -> Result<(), RouterError> {
+        if !self.sessions.contains_key(&clear.neighbor) {
+            return Err(RouterError::invalid(
+                "clear",
+                format!("unknown neighbor {}", clear.neighbor),
+            ));
+        }
+        self.reset_session(&clear.neighbor, "Administrative reset")
+    }
+
+    /// Handles a "route-refresh" message by sending the neighbor all our routes again,
+    /// so that it can apply its changed policies, and withdrawing the ones it may no longer see.
+    /// # Arguments
+    /// * `ip_addr` - The IP address of the neighbor that sent the message.
+    fn handle_route_refresh_message(&mut self, ip_addr: &str) -> Result<(), RouterError> {
+        self.sync_neighbor(ip_addr, true)
+    }
+
+    /// Handles a "refresh" control message by sending a "route-refresh" message to the given neighbor,
+    /// whose routes then go through our import policies again without resetting the session.
+    /// # Arguments
+    /// * `refresh` - The body of the received "refresh" message.
+    fn handle_refresh_message(&self, refresh: &NeighborCommand) -> Result<(), RouterError> {
+        let nei_ip = &refresh.neighbor;
+        if !self.sessions.contains_key(nei_ip) {
+            return Err(RouterError::invalid(
+                "refresh",
+                format!("unknown neighbor {nei_ip}"),
+            ));
+        }
+        let refresh_msg = Message::new(
+            &format!("{}{}", &nei_ip[..nei_ip.len() - 1], "1"),
+            nei_ip,
+            Body::RouteRefresh(Empty {}),
+        );
+        Router::send(&self.sockets[nei_ip], &self.ports[nei_ip], &refresh_msg)
+    }
 }
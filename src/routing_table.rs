@@ -1,15 +1,19 @@
 /// This module contains the implementation of the routing table
 /// and the network struct.
 use crate::{
+    asn,
+    community::{Community, LargeCommunity},
     ipv4::{
         apply_mask, apply_mask_prefix, check_match, divide_prefix, netmask_digit, netnask_increase,
         to_decimal, to_ipv4,
     },
-    router::GLOBAL_TABLE,
+    rpki::RpkiState,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 /// This enum represents the origin of the network
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Serialize, Deserialize)]
 pub enum Origin {
     IGP = 3,
@@ -17,17 +21,48 @@ pub enum Origin {
     UNK = 1,
 }
 
+impl Origin {
+    /// Returns the one-letter origin code used in "show ip bgp" output.
+    pub fn code(&self) -> char {
+        match self {
+            Origin::IGP => 'i',
+            Origin::EGP => 'e',
+            Origin::UNK => '?',
+        }
+    }
+}
+
 /// This struct represents the network
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Network {
-    peer: String,
-    network: String,
-    netmask: String,
-    localpref: i32,
-    selfOrigin: bool,
-    ASPath: Vec<i32>,
-    origin: Origin,
+    pub(crate) peer: String,
+    pub(crate) network: String,
+    pub(crate) netmask: String,
+    pub(crate) localpref: i32,
+    pub(crate) selfOrigin: bool,
+    #[serde(deserialize_with = "asn::deserialize_path")]
+    pub(crate) ASPath: Vec<u32>,
+    pub(crate) origin: Origin,
+    /// The RPKI validation state, only present when the router validates route origins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rpki: Option<RpkiState>,
+    /// The BGP communities attached to the route, kept sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) communities: Vec<Community>,
+    /// The BGP large communities attached to the route, kept sorted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) largeCommunities: Vec<LargeCommunity>,
+    /// The Only-To-Customer attribute (RFC 9234): the AS that limited the route to customers.
+    #[serde(
+        default,
+        deserialize_with = "asn::deserialize_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) otc: Option<u32>,
+    /// Whether the route was detected as a route leak, only set when leaks are flagged.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) leak: bool,
 }
 
 #[allow(non_snake_case)]
@@ -39,7 +74,7 @@ impl Network {
         netmask: String,
         localpref: i32,
         selfOrigin: bool,
-        ASPath: Vec<i32>,
+        ASPath: Vec<u32>,
         origin: Origin,
     ) -> Self {
         Network {
@@ -50,7 +85,42 @@ impl Network {
             selfOrigin,
             ASPath,
             origin,
+            rpki: None,
+            communities: vec![],
+            largeCommunities: vec![],
+            otc: None,
+            leak: false,
+        }
+    }
+
+    /**
+     * This function checks if this network should be preferred over the other one
+     * when both of them have the same prefix length.
+     * The tie-breakers are applied in order: localpref, selfOrigin,
+     * AS path length, origin and finally the lowest peer IP.
+     */
+    pub fn is_preferred_over(&self, other: &Network) -> bool {
+        // Check localpref
+        if self.localpref != other.localpref {
+            return self.localpref > other.localpref;
+        }
+
+        // Check selfOrigin
+        if self.selfOrigin != other.selfOrigin {
+            return self.selfOrigin;
+        }
+
+        // Check ASPath
+        if self.ASPath.len() != other.ASPath.len() {
+            return self.ASPath.len() < other.ASPath.len();
+        }
+
+        // Check origin
+        if self.origin != other.origin {
+            return self.origin > other.origin;
         }
+
+        to_decimal(&self.peer) < to_decimal(&other.peer)
     }
 }
 
@@ -58,24 +128,56 @@ impl Network {
 #[derive(Debug, Clone, Serialize)]
 pub struct Table {
     table: Vec<Network>,
+    /// Whether RPKI invalid routes lose against any other route of the same prefix length.
+    #[serde(skip)]
+    depreference_invalid: bool,
 }
 
 impl Table {
     pub fn new() -> Self {
-        Table { table: vec![] }
+        Table {
+            table: vec![],
+            depreference_invalid: false,
+        }
+    }
+
+    // This is the setter function for depreference_invalid
+    pub fn set_depreference_invalid(&mut self, depreference_invalid: bool) {
+        self.depreference_invalid = depreference_invalid;
+    }
+
+    /**
+     * This function loads a routing table saved as a "table" message.
+     * The file may contain either the whole "table" message or only its "msg" array.
+     * The rows are kept as they are, without aggregating them again.
+     */
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("{e} -> failed to read {path}"))?;
+        let json: Value = serde_json::from_str(&content)
+            .map_err(|e| format!("{e} -> failed to parse JSON object"))?;
+        // Unwrap the "msg" field if the whole message was saved
+        let rows = match json {
+            Value::Object(mut obj) => obj.remove("msg").unwrap_or(Value::Null),
+            other => other,
+        };
+        let table: Vec<Network> = serde_json::from_value(rows)
+            .map_err(|e| format!("{e} -> failed to parse the routing table"))?;
+
+        Ok(Table {
+            table,
+            depreference_invalid: false,
+        })
     }
 
     // This function updates the routing table with the new network.
     pub fn update(&mut self, mut new_net: Network) {
         // Remove the network from the table if it has the same network prefix, subnet mask, and peer IP as the given network.
         self.withdraw(&new_net.network, &new_net.netmask, &new_net.peer);
-        loop {
-            // Whenever we want to add the new row into table,
-            // we aggregate as much as possible
-            match self.aggregate(new_net.clone()) {
-                Some(n) => new_net = n,
-                None => break,
-            }
+        // Whenever we want to add the new row into table,
+        // we aggregate as much as possible
+        while let Some(n) = self.aggregate(new_net.clone()) {
+            new_net = n;
         }
         // Add aggregated row into the table
         self.table.push(new_net)
@@ -114,46 +216,30 @@ impl Table {
 
                     if check_match(&divided_net1, &new_netmask, network) {
                         // Push the unmatched part back to the routing table
-                        self.update(Network::new(
-                            peer.to_string(),
-                            divided_net2,
-                            new_netmask.clone(),
-                            net.localpref.clone(),
-                            net.selfOrigin.clone(),
-                            net.ASPath.clone(),
-                            net.origin.clone(),
-                        ));
-
-                        net = Network::new(
-                            peer.to_string(),
-                            divided_net1,
-                            new_netmask,
-                            net.localpref.clone(),
-                            net.selfOrigin.clone(),
-                            net.ASPath.clone(),
-                            net.origin.clone(),
-                        );
+                        self.update(Network {
+                            network: divided_net2,
+                            netmask: new_netmask.clone(),
+                            ..net.clone()
+                        });
+
+                        net = Network {
+                            network: divided_net1,
+                            netmask: new_netmask,
+                            ..net
+                        };
                     } else {
                         // Push the unmatched part back to the routing table
-                        self.update(Network::new(
-                            peer.to_string(),
-                            divided_net1,
-                            new_netmask.clone(),
-                            net.localpref.clone(),
-                            net.selfOrigin.clone(),
-                            net.ASPath.clone(),
-                            net.origin.clone(),
-                        ));
-
-                        net = Network::new(
-                            peer.to_string(),
-                            divided_net2,
-                            new_netmask,
-                            net.localpref.clone(),
-                            net.selfOrigin.clone(),
-                            net.ASPath.clone(),
-                            net.origin.clone(),
-                        );
+                        self.update(Network {
+                            network: divided_net1,
+                            netmask: new_netmask.clone(),
+                            ..net.clone()
+                        });
+
+                        net = Network {
+                            network: divided_net2,
+                            netmask: new_netmask,
+                            ..net
+                        };
                     }
                 }
                 true
@@ -162,18 +248,62 @@ impl Table {
         }
     }
 
+    /**
+     * This function removes every route learned from the given peer.
+     * Return the number of removed rows.
+     */
+    pub fn flush(&mut self, peer: &str) -> usize {
+        let len = self.table.len();
+        self.table.retain(|net| net.peer != peer);
+        len - self.table.len()
+    }
+
+    /**
+     * This function returns the route of the given peer to the given prefix, if any.
+     * If the route is stored in an aggregated row, the row is narrowed down to the prefix.
+     */
+    pub fn route(&self, network: &str, netmask: &str, peer: &str) -> Option<Network> {
+        self.table
+            .iter()
+            .find(|net| {
+                net.peer == peer
+                    && netmask_digit(&net.netmask) <= netmask_digit(netmask)
+                    && check_match(&net.network, &net.netmask, network)
+            })
+            .map(|net| Network {
+                network: network.to_string(),
+                netmask: netmask.to_string(),
+                ..net.clone()
+            })
+    }
+
     // This is the getter function for table
     pub fn get_table(&self) -> &Vec<Network> {
         &self.table
     }
 
+    /**
+     * This function checks if the first network should be preferred over the second one
+     * when both of them have the same prefix length, taking the RPKI policy of the table into account.
+     */
+    pub fn is_preferred(&self, net: &Network, other: &Network) -> bool {
+        if self.depreference_invalid {
+            let (net_invalid, other_invalid) = (
+                net.rpki == Some(RpkiState::Invalid),
+                other.rpki == Some(RpkiState::Invalid),
+            );
+            if net_invalid != other_invalid {
+                return other_invalid;
+            }
+        }
+
+        net.is_preferred_over(other)
+    }
+
     /**
      * This function returns the best route to the given destination
      */
-    pub fn best_route(dst: &str) -> Result<String, String> {
-        let table = GLOBAL_TABLE
-            .lock()
-            .map_err(|e| format!("{e} -> failed to lock the table (best_route)"))?;
+    pub fn best_route(&self, dst: &str) -> Result<String, String> {
         // Create a default Network
         let mut candidate = Network::new(
             "0".to_string(),
@@ -181,12 +311,12 @@ impl Table {
             "0.0.0.0".to_string(),
             0,
             false,
-            table.table[0].ASPath.clone(),
+            vec![],
             Origin::UNK,
         );
         let mut longest_prefix = 0;
 
-        for net in table.table.iter() {
+        for net in self.table.iter() {
             // let (network, netmask) = (net.network, net.netmask);
             if check_match(&net.network, &net.netmask, dst) {
                 let prefix_length = netmask_digit(&net.netmask);
@@ -194,54 +324,15 @@ impl Table {
                 if prefix_length > longest_prefix {
                     candidate = net.clone();
                     longest_prefix = prefix_length;
-                } else if prefix_length == longest_prefix {
-                    // Check localpref
-                    if candidate.localpref > net.localpref {
-                        continue;
-                    } else if candidate.localpref < net.localpref {
-                        candidate = net.clone();
-                        continue;
-                    }
-
-                    // Check selfOrigin
-                    if candidate.selfOrigin != net.selfOrigin {
-                        if candidate.selfOrigin {
-                            continue;
-                        } else {
-                            candidate = net.clone();
-                            continue;
-                        }
-                    }
-
-                    // Check ASPath
-                    if candidate.ASPath.len() > net.ASPath.len() {
-                        candidate = net.clone();
-                        continue;
-                    } else if candidate.ASPath.len() < net.ASPath.len() {
-                        continue;
-                    }
-
-                    // Check origin
-                    if candidate.origin > net.origin {
-                        continue;
-                    } else if candidate.origin < net.origin {
-                        candidate = net.clone();
-                        continue;
-                    }
-
-                    if to_decimal(&candidate.peer) > to_decimal(&net.peer) {
-                        candidate = net.clone();
-                        continue;
-                    } else {
-                        continue;
-                    }
+                } else if prefix_length == longest_prefix && self.is_preferred(net, &candidate) {
+                    candidate = net.clone();
                 }
             }
         }
         if candidate.peer != "0" {
             Ok(candidate.peer)
         } else {
-            Err(format!("No route"))
+            Err("No route".to_string())
         }
     }
 
@@ -259,15 +350,11 @@ impl Table {
                 let net = self.table.remove(ind);
                 let new_netmask = to_ipv4(to_decimal(&net.netmask) << 1);
                 let new_net_prefix = to_ipv4(apply_mask(&net.network, &new_netmask));
-                let aggregated_net = Network::new(
-                    net.peer,
-                    new_net_prefix,
-                    new_netmask,
-                    net.localpref,
-                    net.selfOrigin,
-                    net.ASPath,
-                    net.origin,
-                );
+                let aggregated_net = Network {
+                    network: new_net_prefix,
+                    netmask: new_netmask,
+                    ..net
+                };
                 Some(aggregated_net)
             }
             None => None,
@@ -307,6 +394,21 @@ impl Table {
             return false;
         }
 
+        // Check if RPKI states are same
+        if net1.rpki != net2.rpki {
+            return false;
+        }
+
+        // Check if communities are same
+        if net1.communities != net2.communities || net1.largeCommunities != net2.largeCommunities {
+            return false;
+        }
+
+        // Check if OTC attributes and leak flags are same
+        if net1.otc != net2.otc || net1.leak != net2.leak {
+            return false;
+        }
+
         // Check if these two networks are numerically adjacent
         if apply_mask_prefix(&net1.network, &net1.netmask)
             .abs_diff(apply_mask_prefix(&net2.network, &net2.netmask))
@@ -0,0 +1,198 @@
+/// This module implements RPKI route origin validation (RFC 6811)
+/// against a set of ROAs loaded from a local JSON or CSV export.
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::Ipv4Addr;
+
+use crate::{
+    asn,
+    ipv4::{check_match, netmask_digit, netmask_from_digit, to_decimal, to_ipv4},
+    routing_table::Network,
+};
+
+/// The validation state of a route.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RpkiState {
+    /// A ROA covers the prefix and authorizes the origin AS.
+    Valid,
+    /// ROAs cover the prefix but none of them authorizes the origin AS and prefix length.
+    Invalid,
+    /// No ROA covers the prefix.
+    NotFound,
+}
+
+impl RpkiState {
+    /// Returns the one-letter validation code used in "show ip bgp" output.
+    pub fn code(&self) -> char {
+        match self {
+            RpkiState::Valid => 'V',
+            RpkiState::Invalid => 'I',
+            RpkiState::NotFound => 'N',
+        }
+    }
+}
+
+/// What the router does with routes that are RPKI invalid.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, ValueEnum)]
+pub enum RpkiPolicy {
+    /// Install invalid routes like any other route, only marking their state.
+    Accept,
+    /// Reject invalid routes.
+    Drop,
+    /// Install invalid routes but prefer any other route of the same prefix length.
+    Depreference,
+}
+
+/// A Route Origin Authorization.
+#[derive(Debug, Clone)]
+struct Roa {
+    /// The authorized prefix.
+    network: String,
+    /// The subnet mask of the authorized prefix.
+    netmask: String,
+    /// The longest prefix length the origin AS may announce.
+    max_length: i32,
+    /// The authorized origin AS.
+    asn: u32,
+}
+
+/// A set of ROAs used to validate route origins.
+#[derive(Debug, Clone)]
+pub struct RoaSet {
+    roas: Vec<Roa>,
+}
+
+impl RoaSet {
+    /**
+     * This function loads a ROA set from a local export.
+     * JSON files hold either an array of ROAs or an object with a "roas" array,
+     * where each ROA has an "asn" ("AS65001", "AS1.0" or 65001), a "prefix" in CIDR notation
+     * and an optional "maxLength".
+     * CSV files hold one "ASN,prefix,maxLength[,trust anchor]" line per ROA.
+     * IPv6 ROAs are skipped.
+     * path: The path of the export.
+     * Return the loaded ROA set.
+     */
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("{e} -> failed to read {path}"))?;
+
+        let mut roas = vec![];
+        if content.trim_start().starts_with(['{', '[']) {
+            let json: Value = serde_json::from_str(&content)
+                .map_err(|e| format!("{e} -> failed to parse JSON object"))?;
+            let entries = match json.get("roas") {
+                Some(roas) => roas.clone(),
+                None => json,
+            };
+            let entries = entries.as_array().ok_or(format!(
+                "Invalid ROA file {path} -> expected an array of ROAs"
+            ))?;
+            for entry in entries.iter() {
+                let asn = field_to_string(&entry["asn"]);
+                let prefix = entry["prefix"].as_str().unwrap_or_default();
+                let max_length = entry
+                    .get("maxLength")
+                    .or(entry.get("max_length"))
+                    .map(field_to_string);
+                if let Some(roa) = Roa::parse(&asn, prefix, max_length.as_deref())? {
+                    roas.push(roa);
+                }
+            }
+        } else {
+            for line in content.lines() {
+                let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+                // Skip empty lines and the header
+                if fields.len() < 2 || asn::parse(fields[0]).is_err() {
+                    continue;
+                }
+                if let Some(roa) = Roa::parse(fields[0], fields[1], fields.get(2).copied())? {
+                    roas.push(roa);
+                }
+            }
+        }
+
+        Ok(RoaSet { roas })
+    }
+
+    /**
+     * This function validates the origin of the given route.
+     * The origin AS is the last AS in the path of the route.
+     * net: The route to validate.
+     * Return the validation state of the route.
+     */
+    pub fn validate(&self, net: &Network) -> RpkiState {
+        let prefix_length = netmask_digit(&net.netmask);
+        let origin = net.ASPath.last().copied();
+        let mut covered = false;
+
+        for roa in self.roas.iter() {
+            // Check if the ROA covers the prefix of the route
+            if netmask_digit(&roa.netmask) > prefix_length
+                || !check_match(&roa.network, &roa.netmask, &net.network)
+            {
+                continue;
+            }
+            covered = true;
+
+            if origin == Some(roa.asn) && prefix_length <= roa.max_length {
+                return RpkiState::Valid;
+            }
+        }
+
+        if covered {
+            RpkiState::Invalid
+        } else {
+            RpkiState::NotFound
+        }
+    }
+}
+
+impl Roa {
+    /// Parses a ROA from its fields, returning None for IPv6 ROAs.
+    fn parse(asn: &str, prefix: &str, max_length: Option<&str>) -> Result<Option<Self>, String> {
+        if prefix.contains(':') {
+            return Ok(None);
+        }
+
+        let asn = asn::parse(asn).map_err(|e| format!("{e} -> invalid ROA ASN"))?;
+        let (network, length) = prefix
+            .split_once('/')
+            .ok_or(format!("Invalid ROA prefix {prefix}"))?;
+        let network: Ipv4Addr = network
+            .parse()
+            .map_err(|e| format!("{e} -> invalid ROA prefix {prefix}"))?;
+        let length: i32 = length
+            .parse()
+            .ok()
+            .filter(|len| (0..=32).contains(len))
+            .ok_or(format!("Invalid ROA prefix {prefix}"))?;
+        let max_length = match max_length {
+            Some(max_length) if !max_length.is_empty() => max_length
+                .parse()
+                .ok()
+                .filter(|len| (length..=32).contains(len))
+                .ok_or(format!("Invalid ROA max length {max_length} for {prefix}"))?,
+            _ => length,
+        };
+        let netmask = netmask_from_digit(length);
+
+        Ok(Some(Roa {
+            // Normalize the prefix so that host bits never get in the way of matching
+            network: to_ipv4(u32::from(network) & to_decimal(&netmask)),
+            netmask,
+            max_length,
+            asn,
+        }))
+    }
+}
+
+/// Returns a JSON field as a string, whether it was written as a string or a number.
+fn field_to_string(field: &Value) -> String {
+    match field {
+        Value::String(field) => field.clone(),
+        field => field.to_string(),
+    }
+}
@@ -0,0 +1,163 @@
+/// This module contains the BGP session kept with each neighbor:
+/// a simplified finite state machine with keepalives and a hold timer.
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// The state of the session with a neighbor.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    /// The session is down.
+    Idle,
+    /// The socket of the neighbor is ready, but no handshake was sent yet.
+    Connect,
+    /// Our handshake was sent, and we are waiting for the neighbor to open the session.
+    OpenSent,
+    /// The session is up, and the routes of the neighbor are accepted.
+    Established,
+}
+
+/// The session with a neighbor.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: SessionState,
+    /// The hold time we propose, in seconds. Zero disables keepalives and the hold timer.
+    local_hold_time: u64,
+    /// The hold time negotiated with the neighbor when the session was established.
+    hold_time: u64,
+    /// When the last message was received from the neighbor.
+    last_received: Instant,
+    /// When the last handshake or keepalive was sent to the neighbor.
+    last_sent: Instant,
+    /// Whether the neighbor takes part in the handshakes, so that only a handshake opens its session.
+    negotiated: bool,
+}
+
+impl Session {
+    // This function creates an idle session that proposes the given hold time.
+    pub fn new(local_hold_time: u64) -> Self {
+        let now = Instant::now();
+        Session {
+            state: SessionState::Idle,
+            local_hold_time,
+            hold_time: 0,
+            last_received: now,
+            last_sent: now,
+            negotiated: false,
+        }
+    }
+
+    // This is the getter function for state
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    // This is the getter function for the hold time we propose
+    pub fn local_hold_time(&self) -> u64 {
+        self.local_hold_time
+    }
+
+    // This function moves an idle session to Connect, once the socket of the neighbor is ready.
+    pub fn connect(&mut self) {
+        if self.state == SessionState::Idle {
+            self.state = SessionState::Connect;
+        }
+    }
+
+    // This function moves the session to OpenSent, once our handshake was sent.
+    pub fn open_sent(&mut self, now: Instant) {
+        if self.state == SessionState::Connect {
+            self.state = SessionState::OpenSent;
+            self.last_sent = now;
+        }
+    }
+
+    /**
+     * This function records a message other than a handshake received from the neighbor,
+     * which restarts the hold timer. A neighbor that never sent a handshake or a notification
+     * opens the session implicitly with such a message, as if it had sent a handshake.
+     * now: When the message was received.
+     * Return true if the session just got established. Otherwise, false.
+     */
+    pub fn received(&mut self, now: Instant) -> bool {
+        self.last_received = now;
+        if self.state == SessionState::Established || self.negotiated {
+            return false;
+        }
+        self.establish(None);
+        true
+    }
+
+    /**
+     * This function records a handshake received from the neighbor, which (re-)establishes the session.
+     * remote_hold_time: The hold time proposed by the neighbor, if any.
+     * now: When the handshake was received.
+     * Return true if our handshake was not sent since the session went down,
+     * so that it must be sent to the neighbor. Otherwise, false.
+     */
+    pub fn open(&mut self, remote_hold_time: Option<u64>, now: Instant) -> bool {
+        let answer = matches!(self.state, SessionState::Idle | SessionState::Connect);
+        self.last_received = now;
+        self.negotiated = true;
+        self.establish(remote_hold_time);
+        answer
+    }
+
+    // This function establishes the session with the smallest proposed hold time.
+    fn establish(&mut self, remote_hold_time: Option<u64>) {
+        // A neighbor that proposes nothing accepts our hold time
+        let remote_hold_time = remote_hold_time.unwrap_or(self.local_hold_time);
+        self.hold_time = self.local_hold_time.min(remote_hold_time);
+        self.state = SessionState::Established;
+    }
+
+    /**
+     * This function checks if routes may be sent to the neighbor, which requires the session
+     * to be established. A neighbor that never sent a handshake or a notification does not
+     * take part in the handshakes, so it gets routes as soon as our handshake was sent.
+     * Return true if routes may be sent to the neighbor. Otherwise, false.
+     */
+    pub fn receives_routes(&self) -> bool {
+        match self.state {
+            SessionState::Established => true,
+            SessionState::OpenSent => !self.negotiated,
+            SessionState::Idle | SessionState::Connect => false,
+        }
+    }
+
+    // This function records a notification received from the neighbor, which closes the session until its next handshake.
+    pub fn closed(&mut self) {
+        self.negotiated = true;
+    }
+
+    // This function records a keepalive sent to the neighbor.
+    pub fn keepalive_sent(&mut self, now: Instant) {
+        self.last_sent = now;
+    }
+
+    // This function takes the session down, so that it has to be opened again.
+    pub fn down(&mut self) {
+        self.state = SessionState::Idle;
+        self.hold_time = 0;
+    }
+
+    // This function returns when the hold timer expires, if it runs.
+    pub fn hold_deadline(&self) -> Option<Instant> {
+        match self.state {
+            SessionState::Established if self.hold_time > 0 => {
+                Some(self.last_received + Duration::from_secs(self.hold_time))
+            }
+            _ => None,
+        }
+    }
+
+    // This function returns when the next keepalive is due, a third of the hold time after the last one.
+    pub fn keepalive_deadline(&self) -> Option<Instant> {
+        match self.state {
+            SessionState::Established if self.hold_time > 0 => {
+                Some(self.last_sent + Duration::from_secs(self.hold_time) / 3)
+            }
+            _ => None,
+        }
+    }
+}
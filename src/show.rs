@@ -0,0 +1,71 @@
+/// This module renders the routing table in the style of Cisco's
+/// "show ip bgp" command.
+use crate::{
+    asn::AsNotation,
+    ipv4::{netmask_digit, to_decimal},
+    routing_table::{Network, Table},
+};
+
+/**
+ * This function renders the given routing table as "show ip bgp" text.
+ * Every route is marked as valid ('*') and the preferred route of each
+ * prefix is additionally marked as best ('>'). Routes whose origin was
+ * validated are prefixed with their RPKI validation code.
+ * table: The routing table.
+ * notation: How the AS numbers of the paths are written.
+ * Return the rendered table.
+ */
+pub fn render_table(table: &Table, notation: AsNotation) -> String {
+    let mut rows: Vec<&Network> = table.get_table().iter().collect();
+    // Order the rows by prefix, then by prefix length
+    rows.sort_by_key(|net| (to_decimal(&net.network), netmask_digit(&net.netmask)));
+
+    let mut output = String::new();
+    output.push_str("Status codes: * valid, > best\n");
+    output.push_str("Origin codes: i - IGP, e - EGP, ? - incomplete\n");
+    output.push_str("RPKI validation codes: V valid, I invalid, N Not found\n\n");
+    output.push_str(&format!(
+        "    {:<18} {:<16} {:>6} Path\n",
+        "Network", "Next Hop", "LocPrf"
+    ));
+
+    for net in rows.iter() {
+        // A row is the best path if no other row of the same prefix is preferred over it
+        let best = !rows.iter().any(|other| {
+            other.network == net.network
+                && other.netmask == net.netmask
+                && !std::ptr::eq(*other, *net)
+                && table.is_preferred(other, net)
+        });
+        let path: Vec<String> = net.ASPath.iter().map(|asn| notation.format(*asn)).collect();
+        let mut path = path.join(" ");
+        if !path.is_empty() {
+            path.push(' ');
+        }
+        path.push(net.origin.code());
+        let cidr = format!("{}/{}", net.network, netmask_digit(&net.netmask));
+
+        output.push_str(&format!(
+            "{}*{} {:<18} {:<16} {:>6} {}\n",
+            net.rpki.map(|state| state.code()).unwrap_or(' '),
+            if best { '>' } else { ' ' },
+            cidr,
+            net.peer,
+            net.localpref,
+            path
+        ));
+    }
+
+    output
+}
+
+/**
+ * This function renders a saved "table" message as "show ip bgp" text.
+ * path: The path of the saved message.
+ * notation: How the AS numbers of the paths are written.
+ * Return the rendered table.
+ */
+pub fn render_table_file(path: &str, notation: AsNotation) -> Result<String, String> {
+    let table = Table::load(path)?;
+    Ok(render_table(&table, notation))
+}